@@ -0,0 +1,107 @@
+use heapless::Vec;
+
+use crate::{Instructions, Instructions::*, Operand, Ptr};
+
+/// The most basic blocks `basic_blocks` splits a stream into
+pub const MAX_BASIC_BLOCKS: usize = 64;
+
+/// A run of instructions which is only entered at its start and only left at its end
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasicBlock {
+    pub start: Ptr,
+    /// The address just past the block's last instruction
+    pub end: Ptr,
+    /// Where control can go once the block ends, a taken branch ahead of any fall through
+    pub successors: Vec<Ptr, 2>,
+}
+
+/// Splits the instructions in `mem[start..start + len]` into basic blocks, in address
+/// order. Blocks start at the beginning of the stream, at any jump, branch or call target
+/// inside it and after any instruction which ends a block. Indirect targets, such as
+/// those of `CallReg` or a `Switch` table, aren't known and so aren't followed. A stream
+/// with more than `MAX_BASIC_BLOCKS` blocks has the rest left off
+pub fn basic_blocks(mem: &[u8], start: Ptr, len: usize) -> Vec<BasicBlock, MAX_BASIC_BLOCKS> {
+    let begin = start.0 as usize;
+    let end = (begin + len).min(mem.len());
+    let mem = &mem[..end];
+
+    let mut leaders: Vec<usize, MAX_BASIC_BLOCKS> = Vec::new();
+    let _ = leaders.push(begin);
+    let mut pos = begin;
+    while pos < end {
+        let (next, successors) = step(mem, pos);
+        if let Some(successors) = successors {
+            for leader in successors.iter().map(|addr| addr.0 as usize).chain([next]) {
+                if (begin..end).contains(&leader) && !leaders.contains(&leader) {
+                    let _ = leaders.push(leader);
+                }
+            }
+        }
+        pos = next;
+    }
+
+    let mut blocks = Vec::new();
+    let mut block_start = begin;
+    let mut pos = begin;
+    while pos < end {
+        let (next, successors) = step(mem, pos);
+        let successors = match successors {
+            Some(successors) => successors,
+            // Falling into the start of another block ends this one
+            None if next >= end || leaders.contains(&next) => {
+                let mut successors = Vec::new();
+                let _ = successors.push(Ptr(next as u16));
+                successors
+            }
+            None => {
+                pos = next;
+                continue;
+            }
+        };
+        let block = BasicBlock {
+            start: Ptr(block_start as u16),
+            end: Ptr(next as u16),
+            successors,
+        };
+        if blocks.push(block).is_err() {
+            break;
+        }
+        block_start = next;
+        pos = next;
+    }
+    blocks
+}
+
+/// Decodes the instruction at `pos`, returning where the next one starts and, when it
+/// ends a block, where control can go from it. Bytes which don't decode end a block
+/// with nowhere to go
+fn step(mem: &[u8], pos: usize) -> (usize, Option<Vec<Ptr, 2>>) {
+    let instruction = match Instructions::try_from(mem[pos]) {
+        Ok(instruction) if pos + instruction.encoded_len() <= mem.len() => instruction,
+        _ => return (pos + 1, Some(Vec::new())),
+    };
+    let next = pos + instruction.encoded_len();
+    let fall_through = Ptr(next as u16);
+    let relative = |offset: u16| Ptr((next as u16).wrapping_add_signed(offset as i16));
+    let operands = match instruction.decode_operands(&mem[pos + 1..next]) {
+        Ok(operands) => operands,
+        Err(_) => return (next, Some(Vec::new())),
+    };
+    let operand = |index: usize| match operands[index] {
+        Operand::Lit16(word) | Operand::Addr(Ptr(word)) => word,
+        Operand::Reg(_) | Operand::Lit8(_) => unreachable!("only word operands are targets"),
+    };
+    let targets: &[Ptr] = match instruction {
+        JmpNotEq => &[Ptr(operand(1)), fall_through],
+        JmpLtSigned | JmpGeSigned | JmpAccZero | JmpAccNonZero => &[Ptr(operand(0)), fall_through],
+        JmpRel => &[relative(operand(0))],
+        JmpRelNotEq => &[relative(operand(1)), fall_through],
+        Switch => &[Ptr(operand(3))],
+        TailCall => &[Ptr(operand(0))],
+        CallLit | CallLitArgs => &[Ptr(operand(0)), fall_through],
+        CallReg => &[fall_through],
+        Ret | Iret | Hlt => &[],
+        _ => return (next, None),
+    };
+    (next, Some(targets.iter().copied().collect()))
+}
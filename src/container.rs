@@ -0,0 +1,74 @@
+use heapless::Vec;
+
+use crate::{LoadError, Machine, MachineError, Ptr};
+
+/// The bytes every container starts with
+pub const CONTAINER_MAGIC: [u8; 4] = *b"T16V";
+
+/// The container layout written by `build_container` and read by `load_container`
+pub const CONTAINER_VERSION: u8 = 1;
+
+/// Magic, version, segment count and entry point
+const HEADER_LENGTH: usize = 4 + 1 + 1 + 2;
+
+/// Each segment's address and length, ahead of its bytes
+const SEGMENT_HEADER_LENGTH: usize = 2 + 2;
+
+/// Packs segments into a container which starts running at `entry` once loaded. Words
+/// are big endian like instruction operands, and segments follow the header in order,
+/// each as its address, its length and then its bytes
+pub fn build_container<const N: usize>(
+    entry: Ptr,
+    segments: &[(Ptr, &[u8])],
+) -> Result<Vec<u8, N>, MachineError> {
+    let count = u8::try_from(segments.len()).map_err(|_| MachineError::ProgramTooLarge)?;
+    let mut bytes = Vec::new();
+    let mut extend = |data: &[u8]| {
+        bytes
+            .extend_from_slice(data)
+            .map_err(|_| MachineError::ProgramTooLarge)
+    };
+    extend(&CONTAINER_MAGIC)?;
+    extend(&[CONTAINER_VERSION, count])?;
+    extend(&entry.0.to_be_bytes())?;
+    for (addr, data) in segments {
+        let len = u16::try_from(data.len()).map_err(|_| MachineError::ProgramTooLarge)?;
+        extend(&addr.0.to_be_bytes())?;
+        extend(&len.to_be_bytes())?;
+        extend(data)?;
+    }
+    Ok(bytes)
+}
+
+impl<const MEMORY: usize, const EXTRA_GPR: usize> Machine<MEMORY, EXTRA_GPR> {
+    /// Writes the segments of a container from `build_container` into memory, returning
+    /// the entry point to start running from. Segments are written as they're read, so
+    /// an error part way through leaves the earlier segments loaded
+    pub fn load_container(&mut self, bytes: &[u8]) -> Result<Ptr, LoadError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(LoadError::TruncatedContainer);
+        }
+        if bytes[..4] != CONTAINER_MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        if bytes[4] != CONTAINER_VERSION {
+            return Err(LoadError::UnsupportedVersion(bytes[4]));
+        }
+        let count = bytes[5];
+        let entry = Ptr(u16::from_be_bytes([bytes[6], bytes[7]]));
+        let mut rest = &bytes[HEADER_LENGTH..];
+        for _segment in 0..count {
+            if rest.len() < SEGMENT_HEADER_LENGTH {
+                return Err(LoadError::TruncatedContainer);
+            }
+            let addr = Ptr(u16::from_be_bytes([rest[0], rest[1]]));
+            let len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            let data = rest[SEGMENT_HEADER_LENGTH..]
+                .get(..len)
+                .ok_or(LoadError::TruncatedContainer)?;
+            self.load_program(addr, data)?;
+            rest = &rest[SEGMENT_HEADER_LENGTH + len..];
+        }
+        Ok(entry)
+    }
+}
@@ -0,0 +1,87 @@
+use crate::Ptr;
+
+/// Peripherals which can be mapped onto a word of the machine's address space
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Device {
+    /// Counts machine steps, advancing its tick count once every `period` steps
+    Timer { period: u16, steps: u16, ticks: u16 },
+    /// Produces a repeatable pseudo random sequence from a xorshift generator,
+    /// advancing it on every read
+    Random { state: u16 },
+}
+
+/// Xorshift gets stuck at zero, so a zero seed is swapped for this instead
+const ZERO_SEED_REPLACEMENT: u16 = 0xACE1;
+
+impl Device {
+    pub fn timer(period: u16) -> Self {
+        Device::Timer {
+            period: period.max(1),
+            steps: 0,
+            ticks: 0,
+        }
+    }
+
+    pub fn random(seed: u16) -> Self {
+        Device::Random {
+            state: Self::seed(seed),
+        }
+    }
+
+    fn seed(seed: u16) -> u16 {
+        if seed == 0 {
+            ZERO_SEED_REPLACEMENT
+        } else {
+            seed
+        }
+    }
+
+    /// The value a program sees when reading from the device
+    pub fn read(&mut self) -> u16 {
+        match self {
+            Device::Timer { ticks, .. } => *ticks,
+            Device::Random { state } => {
+                *state ^= *state << 7;
+                *state ^= *state >> 9;
+                *state ^= *state << 8;
+                *state
+            }
+        }
+    }
+
+    /// Handles a program writing a value to the device
+    pub fn write(&mut self, value: u16) {
+        match self {
+            Device::Timer { steps, ticks, .. } => {
+                *steps = 0;
+                *ticks = value;
+            }
+            Device::Random { state } => *state = Self::seed(value),
+        }
+    }
+
+    /// Advances the device by a single machine step
+    pub fn tick(&mut self) {
+        match self {
+            Device::Timer {
+                period,
+                steps,
+                ticks,
+            } => {
+                *steps += 1;
+                if *steps >= *period {
+                    *steps = 0;
+                    *ticks = ticks.wrapping_add(1);
+                }
+            }
+            Device::Random { .. } => {}
+        }
+    }
+}
+
+/// A device along with the address of the word it is mapped onto
+#[derive(Clone, Debug)]
+pub struct MappedDevice {
+    pub addr: Ptr,
+    pub device: Device,
+}
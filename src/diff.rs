@@ -0,0 +1,94 @@
+use heapless::Vec;
+
+use crate::{Machine, Ptr, Registers, VMSize, REGISTER_COUNT};
+
+/// The most changed memory cells a diff records before it is marked truncated
+pub const DIFF_MEMORY_LENGTH: usize = 64;
+
+/// Memory is compared a chunk at a time so unchanged regions are skipped quickly
+const DIFF_CHUNK_LENGTH: usize = 256;
+
+/// A register whose value differs between two machines
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegisterChange {
+    pub register: Registers,
+    pub old: VMSize,
+    pub new: VMSize,
+}
+
+/// One of the extra general purpose registers beyond R8 whose value differs between two
+/// machines, indexed from the first of them
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExtraRegisterChange {
+    pub index: usize,
+    pub old: VMSize,
+    pub new: VMSize,
+}
+
+/// A byte of memory whose value differs between two machines
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryChange {
+    pub addr: Ptr,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// The differences between two machines, from the first machine's state to the second's
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct MachineDiff<const EXTRA_GPR: usize = 0> {
+    pub registers: Vec<RegisterChange, { REGISTER_COUNT as usize }>,
+    pub extra_registers: Vec<ExtraRegisterChange, EXTRA_GPR>,
+    pub memory: Vec<MemoryChange, DIFF_MEMORY_LENGTH>,
+    /// More memory changed than could be recorded
+    pub truncated: bool,
+}
+
+impl<const EXTRA_GPR: usize> MachineDiff<EXTRA_GPR> {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.extra_registers.is_empty() && self.memory.is_empty()
+    }
+}
+
+impl<const MEMORY: usize, const EXTRA_GPR: usize> Machine<MEMORY, EXTRA_GPR> {
+    /// Reports the registers and memory cells which changed going from this machine to `other`
+    pub fn diff(&self, other: &Self) -> MachineDiff<EXTRA_GPR> {
+        let mut diff = MachineDiff::default();
+        for (id, (&old, &new)) in self.registers.iter().zip(&other.registers).enumerate() {
+            if old != new {
+                let register = Registers::try_from(id as u8)
+                    .expect("index should not be able to exceed register count");
+                // Capacity matches the register count so this can't fail
+                let _ = diff.registers.push(RegisterChange { register, old, new });
+            }
+        }
+        let extra_registers = self.extra_registers.iter().zip(&other.extra_registers);
+        for (index, (&old, &new)) in extra_registers.enumerate() {
+            if old != new {
+                // Capacity matches the extra register count so this can't fail
+                let _ = diff
+                    .extra_registers
+                    .push(ExtraRegisterChange { index, old, new });
+            }
+        }
+        let chunks = self
+            .memory
+            .chunks(DIFF_CHUNK_LENGTH)
+            .zip(other.memory.chunks(DIFF_CHUNK_LENGTH));
+        for (chunk, (old_chunk, new_chunk)) in chunks.enumerate() {
+            if old_chunk == new_chunk {
+                continue;
+            }
+            for (offset, (&old, &new)) in old_chunk.iter().zip(new_chunk).enumerate() {
+                if old == new {
+                    continue;
+                }
+                let addr = Ptr((chunk * DIFF_CHUNK_LENGTH + offset) as VMSize);
+                if diff.memory.push(MemoryChange { addr, old, new }).is_err() {
+                    diff.truncated = true;
+                    return diff;
+                }
+            }
+        }
+        diff
+    }
+}
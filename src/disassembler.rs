@@ -0,0 +1,165 @@
+use core::{
+    fmt::{self, Write},
+    ops::Range,
+};
+
+use heapless::{String, Vec};
+
+use crate::{Instructions, OperandKind, Ptr, Registers};
+
+/// The most data bytes listed on a single `DB` line
+const DATA_BYTES_PER_LINE: usize = 8;
+
+/// The longest line `disassemble_entries` can hold for a single instruction
+pub const LISTING_LINE_LENGTH: usize = 64;
+
+/// Writes a listing of the instructions in `mem[start..start + len]`, one per line.
+/// Addresses found in `symbols` are rendered by name, both as operands and as labels
+/// ahead of the instruction they point at. Bytes which don't decode are listed as `DB`
+pub fn disassemble<W: Write>(
+    out: &mut W,
+    mem: &[u8],
+    start: Ptr,
+    len: usize,
+    symbols: &[(Ptr, &str)],
+) -> fmt::Result {
+    disassemble_with_data(out, mem, start, len, symbols, &[])
+}
+
+/// Variant of `disassemble` which returns each instruction's address, the number of bytes
+/// it occupies and its line, without a trailing newline, so callers can map listings back
+/// to addresses. Symbols name operands but aren't listed as labels of their own. Entries
+/// past the first `N` are left off
+pub fn disassemble_entries<const N: usize>(
+    mem: &[u8],
+    start: Ptr,
+    len: usize,
+    symbols: &[(Ptr, &str)],
+) -> Result<Vec<(Ptr, usize, String<LISTING_LINE_LENGTH>), N>, fmt::Error> {
+    let mut entries = Vec::new();
+    let mut pos = start.0 as usize;
+    let end = (pos + len).min(mem.len());
+    while pos < end && !entries.is_full() {
+        let at = Ptr(pos as u16);
+        let mut line = String::new();
+        let size = disassemble_one(&mut line, &mem[..end], at, symbols)?;
+        line.pop();
+        let _ = entries.push((at, size, line));
+        pos += size;
+    }
+    Ok(entries)
+}
+
+/// Variant of `disassemble` for programs with data mixed into their code. Bytes inside
+/// the `data` ranges are listed as `DB` rather than decoded, and decoding picks up again
+/// cleanly after each range instead of running on out of step with the instructions
+pub fn disassemble_with_data<W: Write>(
+    out: &mut W,
+    mem: &[u8],
+    start: Ptr,
+    len: usize,
+    symbols: &[(Ptr, &str)],
+    data: &[Range<Ptr>],
+) -> fmt::Result {
+    let mut pos = start.0 as usize;
+    let end = (pos + len).min(mem.len());
+    while pos < end {
+        let at = Ptr(pos as u16);
+        if let Some(name) = symbol(symbols, at) {
+            writeln!(out, "{name}:")?;
+        }
+        pos += match data.iter().find(|range| range.contains(&at)) {
+            Some(range) => {
+                let data_end = end.min(range.end.0 as usize);
+                disassemble_data(out, &mem[..data_end], at, symbols)?
+            }
+            None => {
+                // Instructions can't run on into the next data range
+                let code_end = data
+                    .iter()
+                    .map(|range| range.start.0 as usize)
+                    .filter(|&data_start| data_start > pos)
+                    .fold(end, usize::min);
+                disassemble_one(out, &mem[..code_end], at, symbols)?
+            }
+        };
+    }
+    Ok(())
+}
+
+/// Writes a line of data bytes starting at `at`, ending it early at the next symbol so
+/// its label lands on the right line. Returns the number of bytes listed
+fn disassemble_data<W: Write>(
+    out: &mut W,
+    mem: &[u8],
+    at: Ptr,
+    symbols: &[(Ptr, &str)],
+) -> Result<usize, fmt::Error> {
+    let pos = at.0 as usize;
+    let mut count = 1;
+    while count < DATA_BYTES_PER_LINE
+        && pos + count < mem.len()
+        && symbol(symbols, Ptr((pos + count) as u16)).is_none()
+    {
+        count += 1;
+    }
+    write!(out, "{at:?}: DB")?;
+    for (i, byte) in mem[pos..pos + count].iter().enumerate() {
+        out.write_str(if i == 0 { " " } else { ", " })?;
+        write!(out, "{byte:#04X}")?;
+    }
+    out.write_char('\n')?;
+    Ok(count)
+}
+
+/// Writes the listing for the instruction at `at`, returning the number of bytes it occupied
+fn disassemble_one<W: Write>(
+    out: &mut W,
+    mem: &[u8],
+    at: Ptr,
+    symbols: &[(Ptr, &str)],
+) -> Result<usize, fmt::Error> {
+    let pos = at.0 as usize;
+    let opcode = mem[pos];
+    let instruction = match Instructions::try_from(opcode) {
+        Ok(instruction) if pos + instruction.encoded_len() <= mem.len() => instruction,
+        _ => {
+            writeln!(out, "{at:?}: DB {opcode:#04X}")?;
+            return Ok(1);
+        }
+    };
+    write!(out, "{at:?}: {instruction}")?;
+    let mut offset = pos + 1;
+    for (i, kind) in instruction.operand_layout().iter().enumerate() {
+        out.write_str(if i == 0 { " " } else { ", " })?;
+        match kind {
+            OperandKind::Reg => match Registers::try_from(mem[offset]) {
+                Ok(reg) => out.write_str(reg.name())?,
+                Err(_) => write!(out, "{:#04X}", mem[offset])?,
+            },
+            OperandKind::Lit8 => write!(out, "{:#04X}", mem[offset])?,
+            OperandKind::Lit16 => write!(out, "{:#06X}", word(mem, offset))?,
+            OperandKind::Addr => {
+                let addr = Ptr(word(mem, offset));
+                match symbol(symbols, addr) {
+                    Some(name) => out.write_str(name)?,
+                    None => write!(out, "{addr:?}")?,
+                }
+            }
+        }
+        offset += kind.size();
+    }
+    out.write_char('\n')?;
+    Ok(instruction.encoded_len())
+}
+
+fn word(mem: &[u8], offset: usize) -> u16 {
+    (mem[offset] as u16) << 8 | mem[offset + 1] as u16
+}
+
+fn symbol<'a>(symbols: &[(Ptr, &'a str)], addr: Ptr) -> Option<&'a str> {
+    symbols
+        .iter()
+        .find(|(symbol_addr, _)| *symbol_addr == addr)
+        .map(|(_, name)| *name)
+}
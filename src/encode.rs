@@ -0,0 +1,45 @@
+use heapless::Vec;
+
+use crate::{Instructions, Operand, OperandKind, Ptr};
+
+/// The longest encoding of any instruction, `Switch` with its four operands
+pub const MAX_INSTRUCTION_LENGTH: usize = 8;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum AsmError {
+    /// A different number of operands than the instruction reads
+    OperandCount { expected: usize, found: usize },
+    /// An operand of the wrong kind for its position in the instruction
+    OperandKind { index: usize, expected: OperandKind },
+}
+
+/// Encodes a single instruction, checking the operands match its `operand_layout`
+pub fn encode(
+    instruction: Instructions,
+    operands: &[Operand],
+) -> Result<Vec<u8, MAX_INSTRUCTION_LENGTH>, AsmError> {
+    let layout = instruction.operand_layout();
+    if layout.len() != operands.len() {
+        return Err(AsmError::OperandCount {
+            expected: layout.len(),
+            found: operands.len(),
+        });
+    }
+    let mut bytes: Vec<u8, MAX_INSTRUCTION_LENGTH> = Vec::new();
+    // Every layout fits within MAX_INSTRUCTION_LENGTH so the pushes can't fail
+    let _ = bytes.push(instruction.into());
+    for (index, (&expected, operand)) in layout.iter().zip(operands).enumerate() {
+        if operand.kind() != expected {
+            return Err(AsmError::OperandKind { index, expected });
+        }
+        let pushed = match *operand {
+            Operand::Reg(reg) => bytes.push(reg.into()).is_ok(),
+            Operand::Lit8(value) => bytes.push(value).is_ok(),
+            Operand::Lit16(value) | Operand::Addr(Ptr(value)) => {
+                bytes.extend_from_slice(&value.to_be_bytes()).is_ok()
+            }
+        };
+        debug_assert!(pushed);
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,19 @@
+use crate::{Instructions, MemoryChange, Ptr, RegisterChange, StackOp};
+
+/// The most events a machine holds before dropping the oldest to make room
+pub const EVENT_QUEUE_LENGTH: usize = 64;
+
+/// Something a step did, in the order it happened, for debuggers and tracers to follow
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MachineEvent {
+    /// An instruction was fetched from `at` and is about to run. The events which
+    /// follow, up to the next of these, are its effects
+    InstructionExecuted { at: Ptr, instruction: Instructions },
+    /// A byte of memory was written, by an instruction or from outside through `set8`,
+    /// `set16` and the loaders built on them
+    MemoryWritten(MemoryChange),
+    /// A word was pushed onto or popped off the stack
+    Stack(StackOp),
+    /// A register held a different value once the instruction finished, including the IP
+    RegisterChanged(RegisterChange),
+}
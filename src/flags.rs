@@ -0,0 +1,71 @@
+/// Condition flags describing the result of the last flag setting instruction
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flags {
+    /// The result was zero
+    pub zero: bool,
+    /// An unsigned addition carried out of, or subtraction borrowed into, the top bit
+    pub carry: bool,
+    /// The top bit of the result was set, making it negative as a signed value
+    pub negative: bool,
+    /// The result doesn't fit as a two's complement signed value
+    pub overflow: bool,
+}
+
+impl Flags {
+    /// Packs the flags into a word, zero in bit 0 then carry, negative and overflow
+    pub fn bits(self) -> u16 {
+        self.zero as u16
+            | (self.carry as u16) << 1
+            | (self.negative as u16) << 2
+            | (self.overflow as u16) << 3
+    }
+
+    /// Unpacks flags from a word packed by `bits`, ignoring any other bits
+    pub fn from_bits(bits: u16) -> Flags {
+        Flags {
+            zero: bits & 0b0001 != 0,
+            carry: bits & 0b0010 != 0,
+            negative: bits & 0b0100 != 0,
+            overflow: bits & 0b1000 != 0,
+        }
+    }
+
+    /// Flags for `a + b + carry`, returning them alongside the wrapped sum
+    pub fn add(a: u16, b: u16, carry: bool) -> (u16, Flags) {
+        let wide = a as u32 + b as u32 + carry as u32;
+        let result = wide as u16;
+        let flags = Flags {
+            zero: result == 0,
+            carry: wide > u16::MAX as u32,
+            negative: result & 0x8000 != 0,
+            // Overflow when both operands share a sign the result doesn't
+            overflow: (a ^ result) & (b ^ result) & 0x8000 != 0,
+        };
+        (result, flags)
+    }
+
+    /// Flags for `a - b`, returning them alongside the wrapped difference
+    pub fn sub(a: u16, b: u16) -> (u16, Flags) {
+        let (result, borrow) = a.overflowing_sub(b);
+        let (_, overflow) = (a as i16).overflowing_sub(b as i16);
+        let flags = Flags {
+            zero: result == 0,
+            carry: borrow,
+            negative: result & 0x8000 != 0,
+            overflow,
+        };
+        (result, flags)
+    }
+
+    /// Whether the last comparison found its first operand less than the second,
+    /// treating both as signed values
+    pub fn signed_less(self) -> bool {
+        self.negative != self.overflow
+    }
+
+    /// Whether the last comparison found its first operand less than the second,
+    /// treating both as unsigned values
+    pub fn unsigned_less(self) -> bool {
+        self.carry
+    }
+}
@@ -0,0 +1,84 @@
+use heapless::Vec;
+
+use crate::{Machine, MachineError, Ptr};
+
+/// The largest record holds 255 data bytes plus its count, address, type and checksum
+const MAX_RECORD_LENGTH: usize = 255 + 5;
+
+const DATA_RECORD: u8 = 0x00;
+const EOF_RECORD: u8 = 0x01;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum LoadError {
+    /// A line which isn't a well formed record, numbered from 1
+    InvalidRecord { line: usize },
+    /// A record whose bytes don't sum to zero with its checksum
+    BadChecksum { line: usize },
+    /// A record type other than data or end of file, such as extended addressing
+    UnsupportedRecord { record_type: u8, line: usize },
+    /// The image ended without an end of file record
+    MissingEof,
+    /// A container which doesn't start with `CONTAINER_MAGIC`
+    BadMagic,
+    /// A container in a layout other than `CONTAINER_VERSION`
+    UnsupportedVersion(u8),
+    /// A container which ends part way through its header or a segment
+    TruncatedContainer,
+    /// A data record or segment which couldn't be written into memory
+    Machine(MachineError),
+}
+
+impl From<MachineError> for LoadError {
+    fn from(value: MachineError) -> Self {
+        LoadError::Machine(value)
+    }
+}
+
+impl<const MEMORY: usize, const EXTRA_GPR: usize> Machine<MEMORY, EXTRA_GPR> {
+    /// Writes the data records of an Intel HEX image into memory, stopping at the end of
+    /// file record. Records are written as they're parsed, so an error part way through
+    /// leaves the earlier records loaded
+    pub fn load_ihex(&mut self, text: &str) -> Result<(), LoadError> {
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let record = parse_record(line).ok_or(LoadError::InvalidRecord { line: line_no })?;
+            if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err(LoadError::BadChecksum { line: line_no });
+            }
+            let addr = Ptr((record[1] as u16) << 8 | record[2] as u16);
+            let data = &record[4..record.len() - 1];
+            match record[3] {
+                DATA_RECORD => self.load_program(addr, data)?,
+                EOF_RECORD => return Ok(()),
+                record_type => {
+                    return Err(LoadError::UnsupportedRecord {
+                        record_type,
+                        line: line_no,
+                    })
+                }
+            }
+        }
+        Err(LoadError::MissingEof)
+    }
+}
+
+/// Decodes the hex digits of a record, checking its length matches its byte count
+fn parse_record(line: &str) -> Option<Vec<u8, MAX_RECORD_LENGTH>> {
+    let digits = line.strip_prefix(':')?.as_bytes();
+    if digits.len() & 1 != 0 {
+        return None;
+    }
+    let mut record = Vec::new();
+    for pair in digits.chunks(2) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        record.push(u8::from_str_radix(pair, 16).ok()?).ok()?;
+    }
+    if record.len() < 5 || record.len() != record[0] as usize + 5 {
+        return None;
+    }
+    Some(record)
+}
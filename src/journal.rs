@@ -0,0 +1,30 @@
+use heapless::Vec;
+
+use crate::{Flags, FrameSize, MemoryChange, RegisterChange, VMSize, REGISTER_COUNT};
+
+/// The most steps a journaling machine remembers, dropping the oldest beyond that
+pub const JOURNAL_LENGTH: usize = 16;
+
+/// The most memory writes a single step can make and still be undone
+pub const JOURNAL_MEMORY_LENGTH: usize = 48;
+
+/// The state one step changed, with the old values needed to undo it
+#[derive(Clone, Debug)]
+pub struct UndoRecord<const EXTRA_GPR: usize> {
+    /// Only the registers the step changed
+    pub registers: Vec<RegisterChange, { REGISTER_COUNT as usize }>,
+    /// Extra registers are few and rarely change, so they're kept whole
+    pub extra_registers: [VMSize; EXTRA_GPR],
+    /// Every memory write in the order it was made, so undoing runs backwards
+    pub memory: Vec<MemoryChange, JOURNAL_MEMORY_LENGTH>,
+    pub flags: Flags,
+    pub halted: bool,
+    pub exit_code: Option<u8>,
+    pub stack_frame_size: FrameSize,
+    /// The queued interrupt the step serviced instead of running an instruction
+    pub interrupt: Option<u8>,
+    /// The opcode the step counted in the instruction histogram
+    pub opcode: Option<u8>,
+    /// The step wrote more memory than the record could hold
+    pub overflowed: bool,
+}
@@ -1,28 +1,79 @@
 #![cfg_attr(not(test), no_std)]
-#![feature(lint_reasons)]
-#![allow(incomplete_features, reason = "known risk")]
-#![feature(generic_const_exprs)]
 
+use core::fmt;
+
+mod basic_block;
+pub use basic_block::*;
+mod container;
+pub use container::*;
+mod device;
+pub use device::*;
+mod encode;
+pub use encode::*;
+mod event;
+pub use event::*;
+mod diff;
+pub use diff::*;
+mod disassembler;
+pub use disassembler::*;
+mod flags;
+pub use flags::*;
+mod ihex;
+pub use ihex::*;
+mod journal;
+pub use journal::*;
 mod machine;
 pub use machine::*;
 mod memory_window;
 pub use memory_window::*;
+mod operand;
+pub use operand::*;
+mod program_builder;
+pub use program_builder::*;
 mod ptr;
+mod verify;
 use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
 pub use ptr::*;
+pub use verify::*;
 
 pub type VMSize = u16;
 
-pub const REGISTER_COUNT: u8 = Registers::R8 as u8 + 1;
+pub const REGISTER_COUNT: u8 = Registers::ACC2 as u8 + 1;
 pub const DEFAULT_MEMORY_LENGTH: usize = u16::MAX as usize;
+pub const PROTECTED_REGION_COUNT: usize = 8;
+pub const INTERRUPT_QUEUE_LENGTH: usize = 8;
+pub const DEVICE_COUNT: usize = 8;
+pub const INTERRUPT_VECTOR_COUNT: u8 = 16;
+pub const LOOP_HISTORY_LENGTH: usize = 16;
+pub const SYSCALL_COUNT: u8 = 16;
+/// The most steps `run_program` takes waiting for a program to halt
+pub const RUN_PROGRAM_STEP_LIMIT: usize = 10_000;
+/// The most times a fault handler may retry one step before the fault is returned
+pub const FAULT_RETRY_LIMIT: usize = 8;
+/// The most recent stack operations kept while stack logging is enabled
+pub const STACK_LOG_LENGTH: usize = 64;
+/// The most call frames `frames` walks before giving up on the rest of the chain
+pub const MAX_FRAME_DEPTH: usize = 32;
+/// The size of the pages memory accesses are counted in
+pub const PAGE_LENGTH: usize = 256;
+/// Enough pages to cover the whole address space
+pub const PAGE_COUNT: usize = (u16::MAX as usize + 1) / PAGE_LENGTH;
+/// Bytes in a coverage bitset, one bit for every address in the address space
+pub const COVERAGE_LENGTH: usize = (u16::MAX as usize + 1) / 8;
+/// Location of the interrupt vector table, a list of u16 handler addresses
+/// indexed by interrupt number
+pub const IVT_BASE: Ptr = Ptr(0x0040);
 
-#[derive(Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum Instructions {
+    /// Does nothing beyond advancing the IP past the opcode
+    Nop = 0x01,
     MoveLitToReg = 0x10,
     MoveRegToReg = 0x11,
     MoveRegToMem = 0x12,
     MoveMemToReg = 0x13,
+    /// Adds two registers, storing the sum in ACC and setting the flags
     AddRegReg = 0x14,
     /// Evaluates a value and modifies the IP (Instruction Pointer) to a
     /// provided address on not equal
@@ -33,20 +84,221 @@ pub enum Instructions {
     PushReg = 0x18,
     /// Moves the stack points by one value to remove the item at the top
     Pop = 0x19,
+    /// Pushes the general purpose registers R1..=R8 onto the stack, followed by
+    /// any extra general purpose registers the machine has beyond R8
+    PushAll = 0x1A,
+    /// Restores any extra general purpose registers and then R8..=R1 from the stack
+    PopAll = 0x1B,
+    /// Moves the IP by a signed offset relative to the next instruction
+    JmpRel = 0x1C,
+    /// Evaluates a value and moves the IP by a signed offset relative to
+    /// the next instruction on not equal
+    JmpRelNotEq = 0x1D,
+    /// Copies a number of words from a source address to a destination
+    /// address, read from the next three u16 instructions literals
+    MemCopy = 0x1E,
+    /// Fills a number of words at a destination address with a value, read
+    /// from the next three u16 instructions literals
+    MemSet = 0x1F,
+    /// Moves a u8 instructions literal into a register, zero extending it
+    MoveLitToRegByte = 0x20,
+    /// Moves a single byte from memory into a register, zero extending it
+    MoveMemToRegByte = 0x21,
+    /// Moves the low byte of a register into a single byte of memory
+    MoveRegToMemByte = 0x22,
+    /// Moves the IP to the u16 address found at the index held in a register
+    /// within a table of addresses, or to a default address when the index is
+    /// past the end of the table
+    Switch = 0x23,
+    /// Multiplies two registers, storing the low word of the 32-bit product
+    /// in ACC and the high word in ACC2
+    MulRegReg = 0x24,
+    /// Adds two registers, storing the sum in a third destination register and
+    /// setting the flags as AddRegReg does
+    AddRegRegDest = 0x25,
+    /// Stores the smaller of two registers in ACC, comparing them as unsigned values
+    MinRegReg = 0x26,
+    /// Stores the larger of two registers in ACC, comparing them as unsigned values
+    MaxRegReg = 0x27,
+    /// Loads the word at a signed offset from the FP into a register, reaching
+    /// locals pushed below the frame or saved state above it
+    LoadLocal = 0x28,
+    /// Stores a register into the word at a signed offset from the FP
+    StoreLocal = 0x29,
+    /// Subtracts the second register from the first as signed values, storing
+    /// the difference in ACC and setting the flags
+    SubRegRegSigned = 0x2A,
+    /// Compares two registers as signed values, setting the flags as a
+    /// subtraction would without storing the difference
+    CmpSignedRegReg = 0x2B,
+    /// Moves the IP to the provided address when the last comparison found
+    /// its first operand less than the second as signed values
+    JmpLtSigned = 0x2C,
+    /// Moves the IP to the provided address when the last comparison found
+    /// its first operand greater than or equal to the second as signed values
+    JmpGeSigned = 0x2D,
+    /// Adds two registers and the carry flag, storing the sum in ACC and
+    /// setting the flags so wider sums can be chained a word at a time
+    Adc = 0x2E,
+    /// Moves the IP to the provided address when ACC is zero
+    JmpAccZero = 0x2F,
+    /// Moves the IP to the provided address when ACC is not zero
+    JmpAccNonZero = 0x30,
+    /// Divides the first register by the second as unsigned values, storing
+    /// the quotient in ACC and the remainder in ACC2
+    DivRegReg = 0x31,
+    /// Pushes the next u8 instructions literal onto the stack as a single byte
+    PushLit8 = 0x32,
+    /// Pushes the low byte of a register onto the stack as a single byte
+    PushReg8 = 0x33,
+    /// Pops a single byte off the stack into a register, clearing its high byte.
+    /// Calls and interrupts need the stack word aligned, so bytes should be
+    /// pushed and popped in pairs around them
+    Pop8 = 0x34,
+    /// Copies ACC into the register identity provided, a byte shorter than
+    /// moving it out with MoveRegToReg
+    MoveAccToReg = 0x35,
+    /// Pops the top of the stack into ACC
+    PopAcc = 0x36,
+    /// Moves the word at `base + index * 2 + displacement` into a register, taking
+    /// the base and index register identities, a signed u16 displacement literal
+    /// and the destination register identity
+    MoveMemToRegIndexed = 0x37,
+    /// Moves a single byte from memory into a register, sign extending it
+    /// by copying bit 7 into the whole high byte
+    MoveMemToRegByteSigned = 0x38,
+    /// Loads an address into the register identity provided. Runs like MoveLitToReg
+    /// but marks the literal as an address, for reading code and relocating programs
+    Lea = 0x39,
+    /// Exchanges the values of the two register identities provided without
+    /// going through the stack. Neither changes if either write is rejected
+    SwapReg = 0x3A,
+    /// Negates the register identity provided as two's complement, storing the
+    /// result in ACC and leaving the register as it was. Sets the flags as
+    /// subtracting the value from zero would
+    NegReg = 0x3B,
     /// Stashes the current machine state on the stack and moves the IP
-    /// to the location specified from the next u16 instructions literal
+    /// to the location specified from the next u16 instructions literal.
+    /// Calls a subroutine taking no args, recording an arg count of zero
+    /// with the saved state so `Ret` leaves the caller's stack as it was
     CallLit = 0x5E,
     /// Stashes the current machine state on the stack and moves the IP
-    /// to the location specified from the register identity provided
+    /// to the location specified from the register identity provided.
+    /// Takes no args, recording an arg count of zero as CallLit does
     CallReg = 0x5F,
     /// Resets the machine state from the last stack fram values and moves
-    /// the IP back to the prior instruction location
+    /// the IP back to the prior instruction location. ACC is only part of the
+    /// saved state when the machine's `AccSaving` is `CalleeSaved`, otherwise
+    /// subroutines return their result to the caller in ACC
     Ret = 0x60,
-    /// Aborts the machine runtime
+    /// Stashes the current machine state on the stack and moves the IP
+    /// to the handler registered in the interrupt vector table for the
+    /// interrupt number read from the next u8 instructions literal
+    Int = 0x61,
+    /// Returns from an interrupt handler back to the interrupted instruction,
+    /// restoring the ACC and flags saved when the interrupt was taken
+    Iret = 0x62,
+    /// Calls the host handler registered for the syscall number read from the
+    /// next u8 instructions literal. Arguments and results are passed through
+    /// registers or the stack by convention with the handler
+    Syscall = 0x63,
+    /// Moves the IP to the location specified from the next u16 instructions
+    /// literal, discarding the current frame's locals but keeping its saved
+    /// state, so the subroutine's `Ret` returns straight to the caller's caller
+    TailCall = 0x64,
+    /// Variant of CallLit for subroutines taking args, which the caller pushes
+    /// first. The arg count is read from the u8 instructions literal after the
+    /// address and recorded with the saved state, so `Ret` pops the args off again
+    CallLitArgs = 0x65,
+    /// Reserved for a future instruction, decodes but does not execute. Kept out of
+    /// the docs, `from_mnemonic` and `ProgramBuilder`, it's only there so the
+    /// machine has an opcode to report as `Unimplemented`
+    #[doc(hidden)]
+    Reserved = 0xFE,
+    /// Aborts the machine runtime, taking the low byte of ACC as its exit code
     Hlt = 0xFF,
 }
 
-#[derive(Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+impl Instructions {
+    /// The short assembler name of the instruction, as written in listings and assembly.
+    /// Every instruction other than `Reserved` has its own mnemonic, so `from_mnemonic`
+    /// maps it back
+    pub const fn mnemonic(&self) -> &'static str {
+        match self {
+            Instructions::Nop => "NOP",
+            Instructions::MoveLitToReg => "MOV",
+            Instructions::MoveRegToReg => "MOVR",
+            Instructions::MoveRegToMem => "STR",
+            Instructions::MoveMemToReg => "LDR",
+            Instructions::AddRegReg => "ADD",
+            Instructions::JmpNotEq => "JNE",
+            Instructions::PushLit => "PUSH",
+            Instructions::PushReg => "PUSHR",
+            Instructions::Pop => "POP",
+            Instructions::PushAll => "PUSHALL",
+            Instructions::PopAll => "POPALL",
+            Instructions::JmpRel => "JR",
+            Instructions::JmpRelNotEq => "JRNE",
+            Instructions::MemCopy => "MEMCPY",
+            Instructions::MemSet => "MEMSET",
+            Instructions::MoveLitToRegByte => "MOVB",
+            Instructions::MoveMemToRegByte => "LDRB",
+            Instructions::MoveRegToMemByte => "STRB",
+            Instructions::Switch => "SWITCH",
+            Instructions::MulRegReg => "MUL",
+            Instructions::AddRegRegDest => "ADDD",
+            Instructions::MinRegReg => "MIN",
+            Instructions::MaxRegReg => "MAX",
+            Instructions::LoadLocal => "LDL",
+            Instructions::StoreLocal => "STL",
+            Instructions::SubRegRegSigned => "SUBS",
+            Instructions::CmpSignedRegReg => "CMPS",
+            Instructions::JmpLtSigned => "JLT",
+            Instructions::JmpGeSigned => "JGE",
+            Instructions::Adc => "ADC",
+            Instructions::JmpAccZero => "JZ",
+            Instructions::JmpAccNonZero => "JNZ",
+            Instructions::DivRegReg => "DIV",
+            Instructions::PushLit8 => "PUSHB",
+            Instructions::PushReg8 => "PUSHRB",
+            Instructions::Pop8 => "POPB",
+            Instructions::MoveAccToReg => "MOVA",
+            Instructions::PopAcc => "POPA",
+            Instructions::MoveMemToRegIndexed => "LDRX",
+            Instructions::MoveMemToRegByteSigned => "LDRSB",
+            Instructions::Lea => "LEA",
+            Instructions::SwapReg => "SWAP",
+            Instructions::NegReg => "NEG",
+            Instructions::CallLit => "CALL",
+            Instructions::CallReg => "CALLR",
+            Instructions::Ret => "RET",
+            Instructions::Int => "INT",
+            Instructions::Iret => "IRET",
+            Instructions::Syscall => "SYSCALL",
+            Instructions::TailCall => "TCALL",
+            Instructions::CallLitArgs => "CALLA",
+            Instructions::Reserved => "RSVD",
+            Instructions::Hlt => "HLT",
+        }
+    }
+
+    /// Looks up the instruction with the provided mnemonic, which must match exactly.
+    /// Reserved opcodes have no mnemonic to look up
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Instructions> {
+        (0..=u8::MAX)
+            .filter_map(|opcode| Instructions::try_from(opcode).ok())
+            .filter(|instruction| *instruction != Instructions::Reserved)
+            .find(|instruction| instruction.mnemonic() == mnemonic)
+    }
+}
+
+impl fmt::Display for Instructions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.mnemonic())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum Registers {
     /// [IP] Instruction Pointer holds a pointer to the current location
@@ -69,17 +321,128 @@ pub enum Registers {
     R6 = 0x09,
     R7 = 0x0A,
     R8 = 0x0B,
+    /// [ACC2] Secondary accumulator holding the second result of operations
+    /// which produce two, like the high word of a product or a remainder
+    ACC2 = 0x0C,
+}
+
+impl Registers {
+    /// Whether the register tracks machine state (IP, SP, FP) rather than program values
+    pub fn is_reserved(&self) -> bool {
+        matches!(self, Registers::IP | Registers::SP | Registers::FP)
+    }
+
+    /// The canonical name of the register, as written in listings and assembly
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Registers::IP => "IP",
+            Registers::SP => "SP",
+            Registers::FP => "FP",
+            Registers::ACC => "ACC",
+            Registers::R1 => "R1",
+            Registers::R2 => "R2",
+            Registers::R3 => "R3",
+            Registers::R4 => "R4",
+            Registers::R5 => "R5",
+            Registers::R6 => "R6",
+            Registers::R7 => "R7",
+            Registers::R8 => "R8",
+            Registers::ACC2 => "ACC2",
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum MachineError {
-    InvalidInstruction(u8),
+    /// An opcode which doesn't decode to any instruction, along with the
+    /// address it was fetched from
+    InvalidInstruction {
+        opcode: u8,
+        at: Ptr,
+    },
+    /// Operands which don't match the layout of a valid opcode, along with the
+    /// address the instruction was to be written or run at
+    InvalidOperands {
+        opcode: u8,
+        at: Ptr,
+    },
     InvalidRegister(u8),
+    /// A recognized opcode which the machine does not know how to execute
+    Unimplemented(u8),
+    /// An attempt to point SP or FP at an address which is not word aligned
+    MisalignedStackPointer(VMSize),
+    /// A memory access which would run past the end of main memory
+    OutOfBounds(Ptr),
+    /// A write into a region of memory which has been marked read only
+    WriteProtected(Ptr),
+    /// The machine is already tracking as many protected regions as it can hold
+    TooManyProtectedRegions,
+    /// An interrupt number outside of the interrupt vector table
+    InvalidInterrupt(u8),
+    /// The pending interrupt queue has no room for another interrupt
+    InterruptQueueFull,
+    /// A program which doesn't fit in the space provided for it
+    ProgramTooLarge,
+    /// A push which would grow the stack past its limit
+    StackOverflow,
+    /// A buffer too small to hold the bytes requested
+    BufferTooSmall,
+    /// The machine already has as many devices mapped as it can hold
+    TooManyDevices,
+    /// An instruction writing to a reserved register while the machine is strict
+    ReservedRegisterWrite(Registers),
+    /// A syscall number which is out of range or has no handler registered
+    InvalidSyscall(u8),
+    /// A write to address zero while the machine is trapping null writes
+    NullWrite,
+    /// A division with a zero divisor
+    DivideByZero,
+    /// A program which didn't halt within the steps it was allowed
+    StepLimitReached,
+    /// Stack frame bookkeeping which would wrap, as when a frame is torn down further
+    /// than it was built up
+    FrameCorruption,
 }
 
-impl From<TryFromPrimitiveError<Instructions>> for MachineError {
-    fn from(value: TryFromPrimitiveError<Instructions>) -> Self {
-        MachineError::InvalidInstruction(value.number)
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::InvalidInstruction { opcode, at } => {
+                write!(f, "invalid instruction {opcode:#04X} at {at:?}")
+            }
+            MachineError::InvalidOperands { opcode, at } => {
+                write!(
+                    f,
+                    "invalid operands for instruction {opcode:#04X} at {at:?}"
+                )
+            }
+            MachineError::InvalidRegister(id) => write!(f, "invalid register {id:#04X}"),
+            MachineError::Unimplemented(opcode) => {
+                write!(f, "unimplemented instruction {opcode:#04X}")
+            }
+            MachineError::MisalignedStackPointer(value) => {
+                write!(f, "misaligned stack pointer {value:#06X}")
+            }
+            MachineError::OutOfBounds(addr) => write!(f, "out of bounds access at {addr:?}"),
+            MachineError::WriteProtected(addr) => {
+                write!(f, "write to protected memory at {addr:?}")
+            }
+            MachineError::TooManyProtectedRegions => write!(f, "too many protected regions"),
+            MachineError::InvalidInterrupt(int_no) => write!(f, "invalid interrupt {int_no}"),
+            MachineError::InterruptQueueFull => write!(f, "interrupt queue is full"),
+            MachineError::ProgramTooLarge => write!(f, "program too large"),
+            MachineError::StackOverflow => write!(f, "stack overflow"),
+            MachineError::BufferTooSmall => write!(f, "buffer too small"),
+            MachineError::TooManyDevices => write!(f, "too many devices"),
+            MachineError::ReservedRegisterWrite(reg) => {
+                write!(f, "write to reserved register {}", reg.name())
+            }
+            MachineError::InvalidSyscall(number) => write!(f, "invalid syscall {number}"),
+            MachineError::NullWrite => write!(f, "write to null address"),
+            MachineError::DivideByZero => write!(f, "divide by zero"),
+            MachineError::StepLimitReached => write!(f, "step limit reached"),
+            MachineError::FrameCorruption => write!(f, "stack frame corrupted"),
+        }
     }
 }
 
@@ -88,162 +451,3167 @@ impl From<TryFromPrimitiveError<Registers>> for MachineError {
         MachineError::InvalidRegister(value.number)
     }
 }
+
+/// How a bounded run of the machine came to an end
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunOutcome {
+    /// The machine halted after running the given number of steps
+    Halted(usize),
+    /// The step limit was reached without the machine halting
+    StepLimit,
+    /// The machine revisited an earlier state without making any progress
+    SuspectedLoop { at: Ptr },
+    /// The condition being run until held after running the given number of steps
+    Reached(usize),
+}
+
+/// The number of bytes pushed in the current stack frame. Every change is checked, so a
+/// frame torn down further than it was built up fails with `FrameCorruption` rather than
+/// wrapping around
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FrameSize(VMSize);
+
+impl FrameSize {
+    pub const fn new(bytes: VMSize) -> Self {
+        FrameSize(bytes)
+    }
+
+    pub const fn get(self) -> VMSize {
+        self.0
+    }
+
+    pub fn grow(&mut self, bytes: VMSize) -> Result<(), MachineError> {
+        self.0 = self
+            .0
+            .checked_add(bytes)
+            .ok_or(MachineError::FrameCorruption)?;
+        Ok(())
+    }
+
+    pub fn shrink(&mut self, bytes: VMSize) -> Result<(), MachineError> {
+        self.0 = self
+            .0
+            .checked_sub(bytes)
+            .ok_or(MachineError::FrameCorruption)?;
+        Ok(())
+    }
+}
+
+impl PartialEq<VMSize> for FrameSize {
+    fn eq(&self, other: &VMSize) -> bool {
+        self.0 == *other
+    }
+}
+
+/// A call frame found walking the stack, from the FP saved state sits under
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FrameInfo {
+    pub fp: Ptr,
+    /// Where the IP returns to once the frame is popped
+    pub return_addr: Ptr,
+}
+
+/// Memory accesses counted in one page of memory
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PageStats {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// What a fault handler wants done with the step which faulted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FaultAction {
+    /// Run the step again from the IP the handler leaves, which starts out back at the
+    /// faulting instruction, once the handler has dealt with the cause. After
+    /// `FAULT_RETRY_LIMIT` retries of the same step the fault is returned instead
+    Retry,
+    /// Move the IP past the faulting instruction and carry on. A fault raised while
+    /// servicing an interrupt has no instruction to skip, so it's returned instead
+    Skip,
+    /// Return the error from `step` as if there were no handler
+    Propagate,
+}
+
+/// Which way the stack grows through memory as values are pushed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum StackDirection {
+    /// Towards lower addresses, the default
+    #[default]
+    Down,
+    /// Towards higher addresses
+    Up,
+}
+
+/// Who is responsible for ACC across subroutine calls. Interrupts always preserve ACC
+/// whichever is chosen, since the interrupted code never got the chance to save it
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AccSaving {
+    /// The default. Calls leave ACC out of the saved state, so a subroutine can return
+    /// its result in ACC and a caller which needs ACC afterwards must save it first
+    #[default]
+    CallerSaved,
+    /// Calls save ACC along with the general purpose registers and returns restore it,
+    /// so the caller's ACC survives. Results have to come back some other way, such as
+    /// ACC2 or memory, and args sit a word further from FP than they otherwise would
+    CalleeSaved,
+}
+
+/// A word pushed onto or popped off the stack, with the SP left afterwards
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StackOp {
+    Push { value: u16, sp: VMSize },
+    Pop { value: u16, sp: VMSize },
+}
+
 #[cfg(test)]
 mod should {
-    use crate::{Instructions::*, Machine, Ptr, Registers::*, VMSize, DEFAULT_MEMORY_LENGTH};
+    use crate::{
+        backing_len, basic_blocks, build_container, disassemble, disassemble_entries,
+        disassemble_with_data, encode, run_program, verify, AccSaving, AsmError, Device,
+        ExtraRegisterChange, FaultAction, Flags, Instructions::*, LoadError, Machine, MachineError,
+        MemoryChange, ProgramBuilder, Ptr, Registers::*, RunOutcome, StackDirection, StackOp,
+        VMSize, VerifyError, DEFAULT_MEMORY_LENGTH, FAULT_RETRY_LIMIT, IVT_BASE, REGISTER_COUNT,
+    };
 
-    fn print_machine_state(machine: &Machine<DEFAULT_MEMORY_LENGTH>, windows: &[(String, Ptr, VMSize)]) {
+    fn print_machine_state(
+        machine: &Machine<DEFAULT_MEMORY_LENGTH>,
+        windows: &[(String, Ptr, VMSize)],
+    ) {
         let instruction_window = machine.get_window(Ptr(0), 48);
         // let heap_window = machine.get_window(Ptr(256), 24);
-        let stack_window = machine.get_window(Ptr(DEFAULT_MEMORY_LENGTH as VMSize - 48), 48);
+        let stack_window = machine.get_window(Ptr(machine.memory_len() as VMSize - 48), 48);
         println!("\n{machine:?}");
         println!("INSTRUCTIONS:\n{instruction_window:#?}");
         // println!("HEAP:\n{heap_window:#?}");
         println!("STACK:\n{stack_window:#?}");
 
         for window_def in windows {
-            let window = machine.get_window(window_def.1, window_def.2);
+            let window = machine.get_window_clamped(window_def.1, window_def.2);
             println!("WINDOW [{:?}]\n{window:#?}", window_def.0);
         }
     }
 
     #[allow(dead_code)]
-    pub fn counter_program<const MEMORY: usize>(machine: &mut Machine<MEMORY>)
-    where
-        [(); MEMORY * core::mem::size_of::<u8>()]:
-    {
+    pub fn counter_program<const MEMORY: usize>(
+        machine: &mut Machine<MEMORY>,
+    ) -> Result<(), MachineError> {
         let mut i = Ptr(0);
 
-        machine.set8(i.inc(), MoveMemToReg.into());
-        machine.set8(i.inc(), 0x01);
-        machine.set8(i.inc(), 0x00);
-        machine.set8(i.inc(), R1.into());
+        machine.set8(i.inc(), MoveMemToReg.into())?;
+        machine.set8(i.inc(), 0x01)?;
+        machine.set8(i.inc(), 0x00)?;
+        machine.set8(i.inc(), R1.into())?;
 
-        machine.set8(i.inc(), MoveLitToReg.into());
-        machine.set8(i.inc(), 0x00);
-        machine.set8(i.inc(), 0x01);
-        machine.set8(i.inc(), R2.into());
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set8(i.inc(), 0x00)?;
+        machine.set8(i.inc(), 0x01)?;
+        machine.set8(i.inc(), R2.into())?;
 
-        machine.set8(i.inc(), AddRegReg.into());
-        machine.set8(i.inc(), R1.into());
-        machine.set8(i.inc(), R2.into());
+        machine.set8(i.inc(), AddRegReg.into())?;
+        machine.set8(i.inc(), R1.into())?;
+        machine.set8(i.inc(), R2.into())?;
 
-        machine.set8(i.inc(), MoveRegToMem.into());
-        machine.set8(i.inc(), ACC.into());
-        machine.set8(i.inc(), 0x01);
-        machine.set8(i.inc(), 0x00);
+        machine.set8(i.inc(), MoveRegToMem.into())?;
+        machine.set8(i.inc(), ACC.into())?;
+        machine.set8(i.inc(), 0x01)?;
+        machine.set8(i.inc(), 0x00)?;
 
-        machine.set8(i.inc(), JmpNotEq.into());
-        machine.set8(i.inc(), 0x00);
-        machine.set8(i.inc(), 0x03);
-        machine.set8(i.inc(), 0x00);
-        machine.set8(i.inc(), 0x00);
+        machine.set8(i.inc(), JmpNotEq.into())?;
+        machine.set8(i.inc(), 0x00)?;
+        machine.set8(i.inc(), 0x03)?;
+        machine.set8(i.inc(), 0x00)?;
+        machine.set8(i.inc(), 0x00)?;
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn swap_registers_program<const MEMORY: usize>(machine: &mut Machine<MEMORY>)
-    where
-        [(); MEMORY * core::mem::size_of::<u8>()]:
-    {
+    pub fn swap_registers_program<const MEMORY: usize>(
+        machine: &mut Machine<MEMORY>,
+    ) -> Result<(), MachineError> {
         let mut i = Ptr(0);
 
-        machine.set8(i.inc(), MoveLitToReg.into());
-        machine.set8(i.inc(), 0x12);
-        machine.set8(i.inc(), 0x34);
-        machine.set8(i.inc(), R1.into());
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set8(i.inc(), 0x12)?;
+        machine.set8(i.inc(), 0x34)?;
+        machine.set8(i.inc(), R1.into())?;
 
-        machine.set8(i.inc(), MoveLitToReg.into());
-        machine.set8(i.inc(), 0x56);
-        machine.set8(i.inc(), 0x78);
-        machine.set8(i.inc(), R2.into());
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set8(i.inc(), 0x56)?;
+        machine.set8(i.inc(), 0x78)?;
+        machine.set8(i.inc(), R2.into())?;
 
-        machine.set8(i.inc(), PushReg.into());
-        machine.set8(i.inc(), R1.into());
+        machine.set8(i.inc(), PushReg.into())?;
+        machine.set8(i.inc(), R1.into())?;
 
-        machine.set8(i.inc(), PushReg.into());
-        machine.set8(i.inc(), R2.into());
+        machine.set8(i.inc(), PushReg.into())?;
+        machine.set8(i.inc(), R2.into())?;
 
-        machine.set8(i.inc(), Pop.into());
-        machine.set8(i.inc(), R1.into());
+        machine.set8(i.inc(), Pop.into())?;
+        machine.set8(i.inc(), R1.into())?;
 
-        machine.set8(i.inc(), Pop.into());
-        machine.set8(i.inc(), R2.into());
+        machine.set8(i.inc(), Pop.into())?;
+        machine.set8(i.inc(), R2.into())?;
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn stack_frame_program<const MEMORY: usize>(machine: &mut Machine<MEMORY>)
-    where
-        [(); MEMORY * core::mem::size_of::<u8>()]:
-    {
+    pub fn stack_frame_program<const MEMORY: usize>(
+        machine: &mut Machine<MEMORY>,
+    ) -> Result<(), MachineError> {
         let subroutine_addr: u16 = 0x3000;
         let mut i = Ptr(0);
 
         // Populate the stack with some values
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set8(i.inc(), 0x33);
-        machine.set8(i.inc(), 0x33);
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set8(i.inc(), 0x33)?;
+        machine.set8(i.inc(), 0x33)?;
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set8(i.inc(), 0x22);
-        machine.set8(i.inc(), 0x22);
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set8(i.inc(), 0x22)?;
+        machine.set8(i.inc(), 0x22)?;
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set8(i.inc(), 0x11);
-        machine.set8(i.inc(), 0x11);
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set8(i.inc(), 0x11)?;
+        machine.set8(i.inc(), 0x11)?;
 
         // Populate some registers with values to check for restore
 
-        machine.set8(i.inc(), MoveLitToReg.into());
-        machine.set8(i.inc(), 0x12);
-        machine.set8(i.inc(), 0x34);
-        machine.set8(i.inc(), R1.into());
-
-        machine.set8(i.inc(), MoveLitToReg.into());
-        machine.set8(i.inc(), 0x56);
-        machine.set8(i.inc(), 0x78);
-        machine.set8(i.inc(), R4.into());
-
-        // Push arg count of zero
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set8(i.inc(), 0x12)?;
+        machine.set8(i.inc(), 0x34)?;
+        machine.set8(i.inc(), R1.into())?;
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set8(i.inc(), 0x00);
-        machine.set8(i.inc(), 0x00);
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set8(i.inc(), 0x56)?;
+        machine.set8(i.inc(), 0x78)?;
+        machine.set8(i.inc(), R4.into())?;
 
-        machine.set8(i.inc(), CallLit.into());
-        machine.set16(i.inc_by(2), subroutine_addr);
+        machine.set8(i.inc(), CallLit.into())?;
+        machine.set16(i.inc_by(2), subroutine_addr)?;
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set16(i.inc_by(2), 0x4444);
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x4444)?;
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set16(i.inc_by(2), 0x5555);
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x5555)?;
 
         // Subroutine...
         i = Ptr(subroutine_addr);
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set16(i.inc_by(2), 0x0102);
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x0102)?;
+
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x0304)?;
+
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x0506)?;
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x0708)?;
+        machine.set8(i.inc(), R1.into())?;
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x090A)?;
+        machine.set8(i.inc(), R4.into())?;
+
+        machine.set8(i.inc(), Ret.into())?;
+
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x9999)?;
+        Ok(())
+    }
+
+    #[test]
+    fn stop_stepping_once_halted() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        for n in 0..41 {
+            machine.set8(i.inc(), MoveLitToReg.into())?;
+            machine.set16(i.inc_by(2), n)?;
+            machine.set8(i.inc(), R1.into())?;
+        }
+        machine.set8(i.inc(), Hlt.into())?;
+
+        assert_eq!(machine.step_n(1000), Ok(42));
+        assert!(machine.halted);
+        assert_eq!(machine.registers[R1 as usize], 40);
+        assert_eq!(machine.step_n(1000), Ok(0));
+        Ok(())
+    }
+
+    #[test]
+    fn step_over_nops() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        // Shift the program up to make room for the padding
+        machine.memory.copy_within(0..20, 4);
+        let mut i = Ptr(0);
+        for _ in 0..4 {
+            machine.set8(i.inc(), Nop.into())?;
+        }
+
+        let registers = machine.registers;
+        assert_eq!(machine.step_n(4), Ok(4));
+        assert_eq!(machine.registers[IP as usize], 4);
+        assert_eq!(machine.registers[R1 as usize..], registers[R1 as usize..]);
+
+        // One pass through the loop body lands back on the padding
+        assert_eq!(machine.step_n(5), Ok(5));
+        assert_eq!(machine.registers[IP as usize], 0);
+        assert_eq!(machine.get16(Ptr(0x0100)), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn restore_registers_saved_in_bulk() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        for reg in R1 as u8..=R8 as u8 {
+            machine.set8(i.inc(), MoveLitToReg.into())?;
+            machine.set16(i.inc_by(2), 0x1100 * reg as u16)?;
+            machine.set8(i.inc(), reg)?;
+        }
+        machine.set8(i.inc(), PushAll.into())?;
+        for reg in R1 as u8..=R8 as u8 {
+            machine.set8(i.inc(), MoveLitToReg.into())?;
+            machine.set16(i.inc_by(2), 0x0000)?;
+            machine.set8(i.inc(), reg)?;
+        }
+        machine.set8(i.inc(), PopAll.into())?;
+
+        let sp = machine.registers[SP as usize];
+        machine.step_n(17).unwrap();
+        assert_eq!(machine.registers[R1 as usize..=R8 as usize], [0; 8]);
+        machine.step().unwrap();
+        for reg in R1 as usize..=R8 as usize {
+            assert_eq!(machine.registers[reg], 0x1100 * reg as u16);
+        }
+        assert_eq!(machine.registers[SP as usize], sp);
+        Ok(())
+    }
+
+    #[test]
+    fn report_unimplemented_instructions() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.set8(Ptr(0), Reserved.into())?;
+
+        assert_eq!(machine.step(), Err(MachineError::Unimplemented(0xFE)));
+
+        assert_eq!(
+            ProgramBuilder::<16>::new()
+                .nop()
+                .emit(Reserved, &[])
+                .build(),
+            Err(MachineError::Unimplemented(0xFE))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_misaligned_stack_pointers() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x0101)?;
+        machine.set8(i.inc(), SP.into())?;
+
+        let sp = machine.registers[SP as usize];
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::MisalignedStackPointer(0x0101))
+        );
+        assert_eq!(machine.registers[SP as usize], sp);
+        Ok(())
+    }
+
+    #[test]
+    fn jump_by_moving_into_ip() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x0101)?;
+        machine.set8(i.inc(), IP.into())?;
+
+        assert_eq!(machine.step(), Ok(()));
+        assert_eq!(machine.registers[IP as usize], 0x0101);
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_loaded_program() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        assert_eq!(machine.checksum(Ptr(0), 20), Ok(0x05B7_007A));
+        assert_eq!(machine.checksum(Ptr(0), 0), Ok(1));
+        assert_eq!(
+            machine.checksum(Ptr(0xFFF0), 0x20),
+            Err(MachineError::OutOfBounds(Ptr(0xFFF0)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_writes_to_protected_memory() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0xBEEF)?;
+        machine.set8(i.inc(), R1.into())?;
+
+        machine.set8(i.inc(), MoveRegToMem.into())?;
+        machine.set8(i.inc(), R1.into())?;
+        machine.set16(i.inc_by(2), 0x0100)?;
+
+        machine.set8(i.inc(), MoveRegToMem.into())?;
+        machine.set8(i.inc(), R1.into())?;
+        machine.set16(i.inc_by(2), 0x001F)?;
+
+        machine.protect(Ptr(0x0000)..Ptr(0x0020))?;
+
+        machine.step_n(2)?;
+        assert_eq!(machine.get16(Ptr(0x0100)), 0xBEEF);
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::WriteProtected(Ptr(0x001F)))
+        );
+        assert_eq!(machine.get16(Ptr(0x001F)), 0x0000);
+
+        machine.unprotect(Ptr(0x0000)..Ptr(0x0020));
+        assert_eq!(machine.set8(Ptr(0x001F), 0xFF), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn service_interrupts() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let handler_addr: u16 = 0x3000;
+        let mut i = Ptr(0);
+
+        machine.set16(IVT_BASE + 2, handler_addr)?;
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x1111)?;
+        machine.set8(i.inc(), R1.into())?;
+
+        machine.set8(i.inc(), Int.into())?;
+        machine.set8(i.inc(), 0x01)?;
+
+        i = Ptr(handler_addr);
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x9999)?;
+        machine.set8(i.inc(), R1.into())?;
+
+        machine.set8(i.inc(), Iret.into())?;
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set16(i.inc_by(2), 0x0304);
+        let sp = machine.registers[SP as usize];
+        machine.step()?;
+        machine.raise_interrupt(1)?;
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set16(i.inc_by(2), 0x0506);
+        // Hardware interrupts are serviced ahead of the next fetch
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], handler_addr);
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[IP as usize], 0x0004);
+        assert_eq!(machine.registers[R1 as usize], 0x1111);
+        assert_eq!(machine.registers[SP as usize], sp);
 
-        machine.set8(i.inc(), MoveLitToReg.into());
-        machine.set16(i.inc_by(2), 0x0708);
-        machine.set8(i.inc(), R1.into());
+        // Software interrupts go through the same vector table
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], handler_addr);
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[IP as usize], 0x0006);
+        assert_eq!(machine.registers[R1 as usize], 0x1111);
+        assert_eq!(machine.registers[SP as usize], sp);
+        Ok(())
+    }
+
+    #[test]
+    fn keep_the_flags_across_interrupts() -> Result<(), MachineError> {
+        let mut machine = Machine::<1024>::new();
+        let handler_addr = Ptr(0x0200);
+        machine.set_handler(1, handler_addr)?;
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0xFFFF, R1)
+            .mov_lit_reg(0x0001, R2)
+            .cmp_signed(R1, R2)
+            .jlt_signed(Ptr(0x0100))
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(Ptr(0x0100), &[Hlt.into()])?;
+        // The handler compares the other way round, clearing signed_less
+        let handler = ProgramBuilder::<16>::new()
+            .cmp_signed(R2, R1)
+            .iret()
+            .build()?;
+        machine.load_program(handler_addr, &handler)?;
+
+        let sp = machine.registers[SP as usize];
+        machine.step_n(3)?;
+        assert!(machine.flags.signed_less());
+        machine.raise_interrupt(1)?;
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], handler_addr.0);
+        machine.step()?;
+        assert!(!machine.flags.signed_less());
+        machine.step()?;
+        assert!(machine.flags.signed_less());
+        assert_eq!(machine.registers[SP as usize], sp);
+
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], 0x0100);
+        Ok(())
+    }
+
+    #[test]
+    fn keep_acc_across_interrupts_whoever_saves_it() -> Result<(), MachineError> {
+        for acc_saving in [AccSaving::CallerSaved, AccSaving::CalleeSaved] {
+            let mut machine = Machine::<1024>::new();
+            machine.set_acc_saving(acc_saving);
+            let handler_addr = Ptr(0x0200);
+            machine.set_handler(1, handler_addr)?;
+            let program = ProgramBuilder::<16>::new()
+                .mov_lit_reg(0x0040, R1)
+                .add(R1, R1)
+                .push_reg(ACC)
+                .hlt()
+                .build()?;
+            machine.load_program(Ptr(0), &program)?;
+            let handler = ProgramBuilder::<16>::new()
+                .mov_lit_reg(0x1234, ACC)
+                .iret()
+                .build()?;
+            machine.load_program(handler_addr, &handler)?;
+
+            let sp = machine.registers[SP as usize];
+            machine.step_n(2)?;
+            machine.raise_interrupt(1)?;
+            machine.step_n(2)?;
+            assert_eq!(machine.registers[ACC as usize], 0x1234);
+            machine.step()?;
+            assert_eq!(machine.registers[ACC as usize], 0x0080);
+            assert_eq!(machine.registers[SP as usize], sp);
+
+            machine.step()?;
+            assert_eq!(machine.read16(Ptr(machine.registers[SP as usize]))?, 0x0080);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_in_a_small_machine() -> Result<(), MachineError> {
+        let mut machine = Machine::<512>::new();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x0002)?;
+        machine.set8(i.inc(), R1.into())?;
+
+        machine.set8(i.inc(), MoveLitToReg.into())?;
+        machine.set16(i.inc_by(2), 0x0003)?;
+        machine.set8(i.inc(), R2.into())?;
+
+        machine.set8(i.inc(), AddRegReg.into())?;
+        machine.set8(i.inc(), R1.into())?;
+        machine.set8(i.inc(), R2.into())?;
+
+        machine.set8(i.inc(), PushReg.into())?;
+        machine.set8(i.inc(), ACC.into())?;
+
+        machine.set8(i.inc(), Pop.into())?;
+        machine.set8(i.inc(), R3.into())?;
+
+        machine.set8(i.inc(), Hlt.into())?;
+
+        assert_eq!(machine.memory.len(), 512);
+        assert_eq!(machine.step_n(100)?, 6);
+        assert_eq!(machine.registers[R3 as usize], 0x0005);
+        assert_eq!(machine.registers[SP as usize], 510);
+        Ok(())
+    }
+
+    #[test]
+    fn list_stack_words_from_the_top() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x1111)?;
+
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x2222)?;
+
+        machine.set8(i.inc(), PushLit.into())?;
+        machine.set16(i.inc_by(2), 0x3333)?;
+
+        assert_eq!(machine.stack_words().count(), 0);
+        machine.step_n(3)?;
+        assert_eq!(
+            machine.stack_words().collect::<Vec<_>>(),
+            [0x3333, 0x2222, 0x1111]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn jump_relative_to_the_next_instruction() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), Nop.into())?;
+        machine.set8(i.inc(), Nop.into())?;
+        machine.set8(i.inc(), Nop.into())?;
+
+        machine.set8(i.inc(), JmpRel.into())?;
+        machine.set16(i.inc_by(2), -6i16 as u16)?;
+
+        machine.registers[IP as usize] = 0x0003;
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn jump_relative_on_not_equal() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), JmpRelNotEq.into())?;
+        machine.set16(i.inc_by(2), 0x0000)?;
+        machine.set16(i.inc_by(2), 0x0010)?;
+
+        machine.set8(i.inc(), JmpRelNotEq.into())?;
+        machine.set16(i.inc_by(2), 0x0001)?;
+        machine.set16(i.inc_by(2), 0x0010)?;
+
+        // ACC matches, so execution falls through
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], 0x0005);
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], 0x001A);
+        Ok(())
+    }
+
+    #[test]
+    fn copy_memory_blocks() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), MemCopy.into())?;
+        machine.set16(i.inc_by(2), 0x0200)?;
+        machine.set16(i.inc_by(2), 0x0300)?;
+        machine.set16(i.inc_by(2), 0x0004)?;
+
+        // Overlapping copy which shifts the source up by one word
+        machine.set8(i.inc(), MemCopy.into())?;
+        machine.set16(i.inc_by(2), 0x0200)?;
+        machine.set16(i.inc_by(2), 0x0202)?;
+        machine.set16(i.inc_by(2), 0x0004)?;
+
+        // Overlapping copy which shifts the destination back down by one word
+        machine.set8(i.inc(), MemCopy.into())?;
+        machine.set16(i.inc_by(2), 0x0302)?;
+        machine.set16(i.inc_by(2), 0x0300)?;
+        machine.set16(i.inc_by(2), 0x0003)?;
+
+        machine.set8(i.inc(), MemCopy.into())?;
+        machine.set16(i.inc_by(2), 0xFFF0)?;
+        machine.set16(i.inc_by(2), 0x0300)?;
+        machine.set16(i.inc_by(2), 0x0010)?;
+
+        let words = [0x1111, 0x2222, 0x3333, 0x4444];
+        for (n, word) in words.iter().enumerate() {
+            machine.set16(Ptr(0x0200) + n * 2, *word)?;
+        }
+
+        let read_words = |machine: &Machine<DEFAULT_MEMORY_LENGTH>, addr: Ptr, len: usize| {
+            (0..len)
+                .map(|n| machine.get16(addr + n * 2))
+                .collect::<Vec<_>>()
+        };
+
+        machine.step()?;
+        assert_eq!(read_words(&machine, Ptr(0x0300), 4), words);
+        machine.step()?;
+        assert_eq!(
+            read_words(&machine, Ptr(0x0200), 5),
+            [0x1111, 0x1111, 0x2222, 0x3333, 0x4444]
+        );
+        machine.step()?;
+        assert_eq!(
+            read_words(&machine, Ptr(0x0300), 4),
+            [0x2222, 0x3333, 0x4444, 0x4444]
+        );
+        assert_eq!(machine.step(), Err(MachineError::OutOfBounds(Ptr(0xFFF0))));
+        Ok(())
+    }
+
+    #[test]
+    fn fill_memory_blocks() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut i = Ptr(0);
+
+        machine.set8(i.inc(), MemSet.into())?;
+        machine.set16(i.inc_by(2), 0x0200)?;
+        machine.set16(i.inc_by(2), 0xABCD)?;
+        machine.set16(i.inc_by(2), 0x0008)?;
+
+        machine.set8(i.inc(), MemSet.into())?;
+        machine.set16(i.inc_by(2), 0x0300)?;
+        machine.set16(i.inc_by(2), 0xABCD)?;
+        machine.set16(i.inc_by(2), 0x0008)?;
+
+        machine.protect(Ptr(0x0308)..Ptr(0x030A))?;
+
+        machine.step()?;
+        for addr in (0x0200..0x0210).step_by(2) {
+            assert_eq!(machine.get16(Ptr(addr)), 0xABCD);
+        }
+        assert_eq!(machine.get16(Ptr(0x01FE)), 0x0000);
+        assert_eq!(machine.get16(Ptr(0x0210)), 0x0000);
+
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::WriteProtected(Ptr(0x0308)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn describe_errors() {
+        use core::fmt::Write;
+
+        let render = |error: MachineError| {
+            let mut text = heapless::String::<64>::new();
+            write!(text, "{error}").unwrap();
+            text
+        };
+
+        assert_eq!(
+            render(MachineError::InvalidInstruction {
+                opcode: 0xAB,
+                at: Ptr(0x0010)
+            }),
+            "invalid instruction 0xAB at 0x0010"
+        );
+        assert_eq!(
+            render(MachineError::InvalidOperands {
+                opcode: 0x18,
+                at: Ptr(0x0020)
+            }),
+            "invalid operands for instruction 0x18 at 0x0020"
+        );
+        assert_eq!(
+            render(MachineError::InvalidRegister(0x0D)),
+            "invalid register 0x0D"
+        );
+        assert_eq!(
+            render(MachineError::Unimplemented(0xFE)),
+            "unimplemented instruction 0xFE"
+        );
+        assert_eq!(
+            render(MachineError::MisalignedStackPointer(0x0101)),
+            "misaligned stack pointer 0x0101"
+        );
+        assert_eq!(
+            render(MachineError::OutOfBounds(Ptr(0xFFF0))),
+            "out of bounds access at 0xFFF0"
+        );
+        assert_eq!(
+            render(MachineError::WriteProtected(Ptr(0x001F))),
+            "write to protected memory at 0x001F"
+        );
+        assert_eq!(
+            render(MachineError::TooManyProtectedRegions),
+            "too many protected regions"
+        );
+        assert_eq!(
+            render(MachineError::InvalidInterrupt(16)),
+            "invalid interrupt 16"
+        );
+        assert_eq!(
+            render(MachineError::InterruptQueueFull),
+            "interrupt queue is full"
+        );
+        assert_eq!(render(MachineError::ProgramTooLarge), "program too large");
+        assert_eq!(render(MachineError::StackOverflow), "stack overflow");
+        assert_eq!(render(MachineError::BufferTooSmall), "buffer too small");
+        assert_eq!(render(MachineError::TooManyDevices), "too many devices");
+        assert_eq!(
+            render(MachineError::ReservedRegisterWrite(FP)),
+            "write to reserved register FP"
+        );
+        assert_eq!(render(MachineError::InvalidSyscall(3)), "invalid syscall 3");
+        assert_eq!(render(MachineError::NullWrite), "write to null address");
+        assert_eq!(render(MachineError::DivideByZero), "divide by zero");
+        assert_eq!(render(MachineError::StepLimitReached), "step limit reached");
+        assert_eq!(
+            render(MachineError::FrameCorruption),
+            "stack frame corrupted"
+        );
+    }
+
+    #[test]
+    fn report_where_invalid_instructions_live() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new().nop().nop().build()?;
+        machine.load_program(Ptr(0x000E), &program)?;
+        machine.set8(Ptr(0x0010), 0x77)?;
+        machine.registers[IP as usize] = 0x000E;
+
+        assert_eq!(
+            machine.step_n(100),
+            Err(MachineError::InvalidInstruction {
+                opcode: 0x77,
+                at: Ptr(0x0010)
+            })
+        );
+
+        // Blank memory doesn't decode, so a stray jump into it stops straight away
+        machine.registers[IP as usize] = 0x0080;
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::InvalidInstruction {
+                opcode: 0x00,
+                at: Ptr(0x0080)
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_accesses_past_the_end_of_small_memories() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<32>::new()
+            .mov_mem_reg(Ptr(0x1000), R1)
+            .mov_reg_mem(R1, Ptr(0x01FF))
+            .switch(R1, Ptr(0x0400), 1, Ptr(0x0000))
+            .mov_mem_reg_byte(Ptr(0x0200), R1)
+            .build()?;
+        let mut machine = Machine::<512>::new();
+        machine.load_program(Ptr(0), &program)?;
+
+        assert_eq!(machine.step(), Err(MachineError::OutOfBounds(Ptr(0x1000))));
+        machine.registers[IP as usize] = 0x0004;
+        assert_eq!(machine.step(), Err(MachineError::OutOfBounds(Ptr(0x01FF))));
+        machine.registers[IP as usize] = 0x0008;
+        assert_eq!(machine.step(), Err(MachineError::OutOfBounds(Ptr(0x0400))));
+        machine.registers[IP as usize] = 0x0010;
+        assert_eq!(machine.step(), Err(MachineError::OutOfBounds(Ptr(0x0200))));
+        assert_eq!(machine.get_handler(0), Ok(Ptr(0x0000)));
+
+        // Running off the end of memory fails the fetch, even at the very last address
+        machine.registers[IP as usize] = 0x0200;
+        assert_eq!(machine.step(), Err(MachineError::OutOfBounds(Ptr(0x0200))));
+        let mut machine = Machine::default();
+        machine.registers[IP as usize] = 0xFFFF;
+        assert_eq!(machine.step(), Err(MachineError::OutOfBounds(Ptr(0xFFFF))));
+
+        let mut machine = Machine::<64>::new();
+        assert_eq!(
+            machine.get_handler(15),
+            Err(MachineError::OutOfBounds(IVT_BASE + 30))
+        );
+        assert_eq!(
+            machine.set8(Ptr(0x0040), 0xFF),
+            Err(MachineError::OutOfBounds(Ptr(0x0040)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn build_programs_fluently() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        let program = ProgramBuilder::<32>::new()
+            .mov_mem_reg(Ptr(0x0100), R1)
+            .mov_lit_reg(0x0001, R2)
+            .add(R1, R2)
+            .mov_reg_mem(ACC, Ptr(0x0100))
+            .jne(0x0003, Ptr(0x0000))
+            .build()?;
+
+        assert_eq!(program[..], machine.memory[..program.len()]);
+        assert_eq!(
+            ProgramBuilder::<4>::new()
+                .nop()
+                .mov_lit_reg(0x1234, R1)
+                .build(),
+            Err(MachineError::ProgramTooLarge)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn build_programs_from_any_instruction_and_operands() -> Result<(), MachineError> {
+        use crate::Operand;
+
+        let program = ProgramBuilder::<8>::new()
+            .emit(MoveLitToReg, &[Operand::Lit16(0x1234), Operand::Reg(R1)])
+            .hlt()
+            .build()?;
+        assert_eq!(
+            program[..],
+            [MoveLitToReg.into(), 0x12, 0x34, R1.into(), Hlt.into()]
+        );
+
+        // Mismatched operands fail the build rather than being truncated
+        assert_eq!(
+            ProgramBuilder::<8>::new()
+                .nop()
+                .emit(PushReg, &[])
+                .emit(PushLit, &[Operand::Reg(R1)])
+                .build(),
+            Err(MachineError::InvalidOperands {
+                opcode: PushReg.into(),
+                at: Ptr(0x0001)
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mark_registers_in_annotated_windows() -> Result<(), MachineError> {
+        let mut machine = Machine::<512>::new();
+        machine.push(0x1234)?;
+
+        let window = machine.get_window_annotated(Ptr(0x01F8), 8);
+        assert_eq!(
+            format!("{window:?}"),
+            "[0x01F8] ------ ------ ------ ------ SP>------ ------ FP>0x12 0x34"
+        );
+
+        let window = machine.get_window_annotated(Ptr(0x0000), 2);
+        assert_eq!(
+            format!("{window:?}"),
+            "[0x0000] IP>------ ------ _x__ _x__ _x__ _x__ _x__ _x__"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn move_single_bytes() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0xFFFF, R1)
+            .mov_lit_reg_byte(0x41, R1)
+            .mov_lit_reg(0xAB42, R2)
+            .mov_reg_mem_byte(R2, Ptr(0x0100))
+            .mov_mem_reg_byte(Ptr(0x0100), R3)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[R1 as usize], 0x0041);
+        machine.step_n(2)?;
+        assert_eq!(machine.get16(Ptr(0x0100)), 0x4200);
+        machine.step()?;
+        assert_eq!(machine.registers[R3 as usize], 0x0042);
+        Ok(())
+    }
+
+    #[test]
+    fn write_only_the_low_byte_of_a_register() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x0041, R1)
+            .mov_reg_mem_byte(R1, Ptr(0x0081))
+            .build()?;
+        let mut machine = Machine::<256>::new_filled(0xCC);
+        machine.load_program(Ptr(0), &program)?;
+        machine.step()?;
+        let before = machine.clone();
+
+        machine.step()?;
+        let diff = before.diff(&machine);
+        assert_eq!(
+            diff.memory[..],
+            [MemoryChange {
+                addr: Ptr(0x0081),
+                old: 0xCC,
+                new: 0x41
+            }]
+        );
+        assert!(machine
+            .assert_region(Ptr(0x0080), &[0xCC, 0x41, 0xCC])
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn jump_through_switch_tables() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let table = Ptr(0x0200);
+        let targets = [0x1000, 0x2000, 0x3000];
+        let program = ProgramBuilder::<8>::new()
+            .switch(R1, table, targets.len() as u16, Ptr(0x4000))
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        for (n, target) in targets.iter().enumerate() {
+            machine.set16(table + n * 2, *target)?;
+        }
+
+        for (index, target) in [0x1000, 0x2000, 0x3000, 0x4000, 0x4000]
+            .into_iter()
+            .enumerate()
+        {
+            machine.registers[IP as usize] = 0x0000;
+            machine.registers[R1 as usize] = index as u16;
+            machine.step()?;
+            assert_eq!(machine.registers[IP as usize], target);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stop_the_stack_at_its_limit() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        stack_frame_program(&mut machine)?;
+
+        let base = Machine::<DEFAULT_MEMORY_LENGTH>::stack_base();
+        machine.stack_limit = Ptr(base.0 - 6);
+
+        // Three literals fit above the limit
+        machine.step_n(5)?;
+        assert_eq!(machine.registers[SP as usize], base.0 - 6);
+        // So does the arg count the call records, but the rest of its saved state does not
+        assert_eq!(machine.step(), Err(MachineError::StackOverflow));
+        assert_eq!(machine.registers[SP as usize], base.0 - 8);
+        Ok(())
+    }
 
-        machine.set8(i.inc(), MoveLitToReg.into());
-        machine.set16(i.inc_by(2), 0x090A);
-        machine.set8(i.inc(), R4.into());
+    #[test]
+    fn guard_reserved_registers_when_strict() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<8>::new()
+            .push_lit(0x0100)
+            .pop(FP)
+            .build()?;
+
+        let mut machine = Machine::default();
+        machine.load_program(Ptr(0), &program)?;
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[FP as usize], 0x0100);
+
+        let mut machine = Machine::default();
+        machine.strict_registers = true;
+        machine.load_program(Ptr(0), &program)?;
+        machine.step()?;
+        assert_eq!(machine.step(), Err(MachineError::ReservedRegisterWrite(FP)));
+        assert!(FP.is_reserved());
+        assert!(!ACC.is_reserved());
+        Ok(())
+    }
+
+    #[test]
+    fn return_the_executed_instruction() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        assert_eq!(machine.step_returning(), Ok(MoveMemToReg));
+        assert_eq!(machine.step_returning(), Ok(MoveLitToReg));
+        assert_eq!(machine.registers[IP as usize], 0x0008);
+        Ok(())
+    }
+
+    #[test]
+    fn keep_the_high_word_of_products() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1000, R1)
+            .mov_lit_reg(0x1000, R2)
+            .mul(R1, R2)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step_n(3)?;
+        assert_eq!(machine.registers[ACC as usize], 0x0000);
+        assert_eq!(machine.registers[ACC2 as usize], 0x0100);
+        Ok(())
+    }
+
+    #[test]
+    fn read_back_loaded_programs() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0x1234, R1)
+            .push_reg(R1)
+            .hlt()
+            .build()?;
+
+        machine.load_program(Ptr(0x0100), &program)?;
+        let bytes = machine.read_bytes::<32>(Ptr(0x0100), program.len() as VMSize)?;
+        assert_eq!(bytes, program);
+
+        assert_eq!(
+            machine.read_bytes::<4>(Ptr(0x0100), program.len() as VMSize),
+            Err(MachineError::BufferTooSmall)
+        );
+        assert_eq!(
+            machine.read_bytes::<32>(Ptr(0xFFF0), 0x20),
+            Err(MachineError::OutOfBounds(Ptr(0xFFF0)))
+        );
+        assert_eq!(
+            machine.load_program(Ptr(0xFFF0), &[0; 0x20]),
+            Err(MachineError::OutOfBounds(Ptr(0xFFF0)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_programs_without_running_them() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        assert_eq!(verify(&machine.memory, Ptr(0), 20), Ok(()));
+        assert_eq!(
+            verify(&machine.memory, Ptr(0), 18),
+            Err(VerifyError::Truncated { at: Ptr(15) })
+        );
+        assert_eq!(
+            verify(&[PushReg.into(), 0x0D], Ptr(0), 2),
+            Err(VerifyError::InvalidRegister {
+                id: 0x0D,
+                at: Ptr(1)
+            })
+        );
+        assert_eq!(
+            verify(&[Nop.into(), 0x77], Ptr(0), 2),
+            Err(VerifyError::InvalidInstruction {
+                opcode: 0x77,
+                at: Ptr(1)
+            })
+        );
+        assert_eq!(
+            verify(&[Nop.into()], Ptr(0), 2),
+            Err(VerifyError::OutOfBounds(Ptr(0)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn add_into_any_register() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1200, R1)
+            .mov_lit_reg(0x0034, R2)
+            .add_dest(R1, R2, R3)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.registers[ACC as usize] = 0xAAAA;
+
+        machine.step_n(3)?;
+        assert_eq!(machine.registers[R3 as usize], 0x1234);
+        assert_eq!(machine.registers[ACC as usize], 0xAAAA);
+        Ok(())
+    }
+
+    #[test]
+    fn count_steps_with_a_timer() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.map_device(Ptr(0x0300), Device::timer(1))?;
+        machine.map_device(Ptr(0x0302), Device::timer(5))?;
+
+        let program = ProgramBuilder::<16>::new()
+            .mov_mem_reg(Ptr(0x0300), R1)
+            .mov_mem_reg(Ptr(0x0302), R2)
+            .build()?;
+        machine.load_program(Ptr(0), &[Nop.into(); 10])?;
+        machine.load_program(Ptr(10), &program)?;
+
+        machine.step_n(10)?;
+        assert_eq!(machine.read16(Ptr(0x0300))?, 10);
+        assert_eq!(machine.read16(Ptr(0x0302))?, 2);
+
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[R1 as usize], 10);
+        assert_eq!(machine.registers[R2 as usize], 2);
+        // The ports are backed by the devices rather than main memory
+        assert_eq!(machine.get16(Ptr(0x0300)), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn return_values_in_acc() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let subroutine_addr = Ptr(0x3000);
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0x0002, R1)
+            .mov_lit_reg(0x0003, R2)
+            .call_lit(subroutine_addr)
+            .hlt()
+            .build()?;
+        let subroutine = ProgramBuilder::<32>::new()
+            .add(R1, R2)
+            .mov_lit_reg(0x0000, R1)
+            .ret()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(subroutine_addr, &subroutine)?;
+
+        machine.step_n(100)?;
+        assert!(machine.halted);
+        assert_eq!(machine.registers[ACC as usize], 0x0005);
+        assert_eq!(machine.registers[R1 as usize], 0x0002);
+        Ok(())
+    }
+
+    #[test]
+    fn disassemble_with_symbols() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        stack_frame_program(&mut machine)?;
+        let symbols = [(Ptr(0x3000), "sub_3000")];
+
+        let mut listing = String::new();
+        disassemble(&mut listing, &machine.memory, Ptr(0), 26, &symbols).unwrap();
+        assert!(listing.contains("0x0011: CALL sub_3000\n"));
+        assert!(listing.starts_with("0x0000: PUSH 0x3333\n"));
+        assert!(listing.ends_with("0x0017: PUSH 0x5555\n"));
+
+        let mut listing = String::new();
+        disassemble(&mut listing, &machine.memory, Ptr(0x3000), 4, &symbols).unwrap();
+        assert_eq!(listing, "sub_3000:\n0x3000: PUSH 0x0102\n0x3003: DB 0x17\n");
+        Ok(())
+    }
+
+    #[test]
+    fn detect_tight_loops() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x0001, R1)
+            .jmp_rel(-3)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        assert_eq!(machine.run_with_limit(1000, false)?, RunOutcome::StepLimit);
+        assert_eq!(
+            machine.run_with_limit(1000, true)?,
+            RunOutcome::SuspectedLoop { at: Ptr(4) }
+        );
+
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new().nop().nop().hlt().build()?;
+        machine.load_program(Ptr(0), &program)?;
+        assert_eq!(machine.run_with_limit(1000, true)?, RunOutcome::Halted(3));
+        Ok(())
+    }
+
+    #[test]
+    fn pop_exactly_what_was_pushed() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let mut value: u16 = 0xACE1;
+        let mut values = [0u16; 256];
+        for slot in values.iter_mut() {
+            // xorshift to cover a spread of high and low bytes
+            value ^= value << 7;
+            value ^= value >> 9;
+            value ^= value << 8;
+            *slot = value;
+
+            machine.push(value)?;
+            assert_eq!(machine.pop()?, value);
+        }
+
+        for value in values {
+            machine.push(value)?;
+        }
+        for value in values.iter().rev() {
+            assert_eq!(machine.pop()?, *value);
+        }
+        assert_eq!(
+            machine.registers[SP as usize],
+            Machine::<DEFAULT_MEMORY_LENGTH>::stack_base().0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn save_extra_general_purpose_registers() -> Result<(), MachineError> {
+        // R1 to R8 plus another eight
+        let mut machine = Machine::<1024, 8>::new();
+        for reg in R1 as usize..=R8 as usize {
+            machine.registers[reg] = 0x1100 + reg as u16;
+        }
+        for reg in 0..8 {
+            machine.extra_registers[reg] = 0x2200 + reg as u16;
+        }
+
+        machine.push(0)?;
+        machine.push_state()?;
+        machine.registers[R1 as usize..=R8 as usize].fill(0);
+        machine.extra_registers.fill(0);
+        machine.pop_state()?;
+
+        for reg in R1 as usize..=R8 as usize {
+            assert_eq!(machine.registers[reg], 0x1100 + reg as u16);
+        }
+        for reg in 0..8 {
+            assert_eq!(machine.extra_registers[reg], 0x2200 + reg as u16);
+        }
+        assert_eq!(
+            machine.registers[SP as usize],
+            Machine::<1024, 8>::stack_base().0
+        );
+
+        // PushAll and PopAll carry the extra registers too, and diffs report them
+        let program = ProgramBuilder::<8>::new().push_all().pop_all().build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.step()?;
+        let before = machine.clone();
+        machine.extra_registers[7] = 0;
+        let diff = before.diff(&machine);
+        assert_eq!(
+            diff.extra_registers[..],
+            [ExtraRegisterChange {
+                index: 7,
+                old: 0x2207,
+                new: 0
+            }]
+        );
+        machine.step()?;
+        assert_eq!(machine.extra_registers[7], 0x2207);
+        assert!(before.diff(&machine).extra_registers.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn peek_at_the_next_opcode() -> Result<(), MachineError> {
+        let mut machine = Machine::<512>::new();
+        machine.set8(Ptr(0x0120), Hlt.into())?;
+        machine.registers[IP as usize] = 0x0120;
+
+        assert_eq!(machine.peek_opcode()?, Hlt.into());
+        assert_eq!(machine.registers[IP as usize], 0x0120);
+
+        machine.registers[IP as usize] = 0x0200;
+        assert_eq!(
+            machine.peek_opcode(),
+            Err(MachineError::OutOfBounds(Ptr(0x0200)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn clamp_pointers_at_the_edges() {
+        assert_eq!(Ptr(0xFFFE).saturating_add(10), Ptr(0xFFFF));
+        assert_eq!(Ptr(0x0100).saturating_add(0x10), Ptr(0x0110));
+        assert_eq!(Ptr(0x0004).saturating_sub(10), Ptr(0));
+        assert_eq!(Ptr(0x0110).saturating_sub(0x10), Ptr(0x0100));
+    }
+
+    #[test]
+    fn find_byte_patterns() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.load_program(Ptr(0x0400), b"NEEDLE")?;
+
+        assert_eq!(machine.find(b"NEEDLE", Ptr(0)), Some(Ptr(0x0400)));
+        assert_eq!(machine.find(b"NEEDLE", Ptr(0x0400)), Some(Ptr(0x0400)));
+        assert_eq!(machine.find(b"NEEDLE", Ptr(0x0401)), None);
+        assert_eq!(machine.find(b"DLE", Ptr(0)), Some(Ptr(0x0403)));
+        Ok(())
+    }
+
+    #[test]
+    fn pick_the_min_and_max_of_registers() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0x0005, R1)
+            .mov_lit_reg(0x0003, R2)
+            .min(R1, R2)
+            .mov_reg_reg(ACC, R3)
+            .max(R1, R2)
+            .mov_lit_reg(0xFFFF, R4)
+            .max(R4, R1)
+            .mov_reg_reg(ACC, R5)
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step_n(5)?;
+        assert_eq!(machine.registers[R3 as usize], 0x0003);
+        assert_eq!(machine.registers[ACC as usize], 0x0005);
+
+        machine.step_n(10)?;
+        // 0xFFFF is the largest unsigned value rather than -1
+        assert_eq!(machine.registers[R5 as usize], 0xFFFF);
+        Ok(())
+    }
+
+    #[test]
+    fn load_intel_hex_images() -> Result<(), LoadError> {
+        let mut machine = Machine::default();
+        machine.load_ihex(":0401000010123404A1\n:01020000FFFE\n:00000001FF\n")?;
+
+        assert_eq!(machine.get(Ptr(0x0100)), MoveLitToReg.into());
+        assert_eq!(machine.get16(Ptr(0x0101)), 0x1234);
+        assert_eq!(machine.get(Ptr(0x0103)), R1.into());
+        assert_eq!(machine.get(Ptr(0x0200)), Hlt.into());
+
+        assert_eq!(
+            machine.load_ihex(":01020000FFFF\n:00000001FF"),
+            Err(LoadError::BadChecksum { line: 1 })
+        );
+        assert_eq!(
+            machine.load_ihex(":020000040001F9\n:00000001FF"),
+            Err(LoadError::UnsupportedRecord {
+                record_type: 0x04,
+                line: 1
+            })
+        );
+        assert_eq!(
+            machine.load_ihex(":01020000FFFE\n"),
+            Err(LoadError::MissingEof)
+        );
+        assert_eq!(
+            machine.load_ihex("\n:0102FF"),
+            Err(LoadError::InvalidRecord { line: 2 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_and_enter_images() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let image = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1234, R1)
+            .hlt()
+            .build()?;
+        machine.load_image(Ptr(0x0100), &image, Ptr(0x0100))?;
+        assert_eq!(machine.registers[IP as usize], 0x0100);
+
+        assert_eq!(machine.step_returning()?, MoveLitToReg);
+        assert_eq!(machine.registers[R1 as usize], 0x1234);
+
+        assert_eq!(
+            machine.load_image(Ptr(0xFFF0), &[0; 32], Ptr(0xFFF0)),
+            Err(MachineError::OutOfBounds(Ptr(0xFFF0)))
+        );
+        assert_eq!(machine.registers[IP as usize], 0x0104);
+        Ok(())
+    }
+
+    #[test]
+    fn call_host_syscalls() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.register_syscall(0, |machine| machine.set_reg(R1, 0x1234))?;
+        let program = ProgramBuilder::<8>::new().syscall(0).syscall(1).build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step()?;
+        assert_eq!(machine.registers[R1 as usize], 0x1234);
+        assert_eq!(machine.step(), Err(MachineError::InvalidSyscall(1)));
+        assert_eq!(
+            machine.register_syscall(16, |_| Ok(())),
+            Err(MachineError::InvalidSyscall(16))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn address_locals_relative_to_the_frame() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let main = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x4321, R1)
+            .call_lit(Ptr(0x3000))
+            .hlt()
+            .build()?;
+        let subroutine = ProgramBuilder::<32>::new()
+            .push_lit(0x0000)
+            .mov_lit_reg(0xBEEF, R2)
+            .store_local(R2, 0)
+            .load_local(0, R3)
+            .load_local(4, R4)
+            .mov_reg_reg(R3, ACC)
+            .ret()
+            .build()?;
+        machine.load_program(Ptr(0), &main)?;
+        machine.load_program(Ptr(0x3000), &subroutine)?;
+
+        machine.step_n(7)?;
+        let fp = Ptr(machine.registers[FP as usize]);
+        assert_eq!(machine.get16(fp), 0xBEEF);
+        assert_eq!(machine.registers[R3 as usize], 0xBEEF);
+        // The return address saved by the call sits just above the frame size
+        assert_eq!(machine.registers[R4 as usize], 0x0007);
+
+        machine.step_n(3)?;
+        assert!(machine.halted);
+        assert_eq!(machine.registers[ACC as usize], 0xBEEF);
+        assert_eq!(machine.registers[R3 as usize], 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn count_executed_instructions() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        // Three passes around the loop until ACC reaches 3
+        machine.step_n(15)?;
+        let histogram = machine.instruction_histogram();
+        for instruction in [
+            MoveMemToReg,
+            MoveLitToReg,
+            AddRegReg,
+            MoveRegToMem,
+            JmpNotEq,
+        ] {
+            assert_eq!(histogram[u8::from(instruction) as usize], 3);
+        }
+        assert_eq!(histogram.iter().sum::<u64>(), 15);
+        Ok(())
+    }
+
+    #[test]
+    fn compare_signed_values() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0xFFFF, R1)
+            .mov_lit_reg(0x0001, R2)
+            .cmp_signed(R1, R2)
+            .jlt_signed(Ptr(0x0100))
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        let target = ProgramBuilder::<16>::new()
+            .sub_signed(R2, R1)
+            .jge_signed(Ptr(0x0200))
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0x0100), &target)?;
+
+        machine.step_n(3)?;
+        // -1 is less than 1 as signed values but 0xFFFF isn't less than 1
+        assert!(machine.flags.signed_less());
+        assert!(!machine.flags.unsigned_less());
+        assert_eq!(machine.registers[ACC as usize], 0x0000);
+
+        machine.step()?;
+        assert_eq!(machine.registers[IP as usize], 0x0100);
+
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[ACC as usize], 0x0002);
+        assert_eq!(machine.registers[IP as usize], 0x0200);
+
+        // -32768 - 1 overflows to a positive result yet -32768 is still the lesser
+        let (_, flags) = Flags::sub(0x8000, 0x0001);
+        assert!(flags.overflow && !flags.negative);
+        assert!(flags.signed_less());
+        Ok(())
+    }
+
+    #[test]
+    fn format_windows_at_any_width() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.load_program(Ptr(0x0100), &[0xAB; 32])?;
+
+        let wide = format!("{:?}", machine.get_window(Ptr(0x0100), 32).with_width(16));
+        let rows: std::vec::Vec<&str> = wide.lines().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].starts_with("[0x0100] 0xAB"));
+        assert!(rows[1].starts_with("[0x0110] 0xAB"));
+        assert_eq!(rows[1].matches("0xAB").count(), 16);
+
+        let padded = format!("{:?}", machine.get_window(Ptr(0x0100), 20).with_width(16));
+        let last_row = padded.lines().nth(1).unwrap();
+        assert_eq!(last_row.matches("0xAB").count(), 4);
+        assert_eq!(last_row.matches(" _x__").count(), 12);
+        Ok(())
+    }
+
+    #[test]
+    fn diff_machines_across_a_step() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<8>::new()
+            .mov_reg_mem(R1, Ptr(0x0100))
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.registers[R1 as usize] = 0x1234;
+
+        let before = machine.clone();
+        assert!(before.diff(&machine).is_empty());
+        machine.step()?;
+
+        let diff = before.diff(&machine);
+        assert_eq!(diff.registers.len(), 1);
+        assert_eq!(diff.registers[0].register, IP);
+        assert_eq!(
+            (diff.registers[0].old, diff.registers[0].new),
+            (0x0000, 0x0004)
+        );
+        assert_eq!(
+            diff.memory[..],
+            [
+                MemoryChange {
+                    addr: Ptr(0x0100),
+                    old: 0x00,
+                    new: 0x12
+                },
+                MemoryChange {
+                    addr: Ptr(0x0101),
+                    old: 0x00,
+                    new: 0x34
+                },
+            ]
+        );
+        assert!(!diff.truncated);
+        Ok(())
+    }
+
+    #[test]
+    fn add_with_carry_across_words() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        // 0x0000_FFFF + 0x0000_0001, low words in R1 and R2, high words in R3 and R4
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0xFFFF, R1)
+            .mov_lit_reg(0x0001, R2)
+            .add(R1, R2)
+            .mov_reg_reg(ACC, R5)
+            .adc(R3, R4)
+            .mov_reg_reg(ACC, R6)
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step_n(3)?;
+        assert_eq!(machine.registers[ACC as usize], 0x0000);
+        assert!(machine.flags.carry);
+
+        machine.step_n(10)?;
+        assert_eq!(machine.registers[R5 as usize], 0x0000);
+        assert_eq!(machine.registers[R6 as usize], 0x0001);
+        assert!(!machine.flags.carry);
+        Ok(())
+    }
+
+    #[test]
+    fn carry_out_of_adds_into_a_destination_register() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0xFFFF, R1)
+            .mov_lit_reg(0x0001, R2)
+            .add(R1, R2)
+            .add_dest(R3, R4, R5)
+            .adc(R3, R4)
+            .mov_reg_reg(ACC, R6)
+            .add_dest(R1, R2, R5)
+            .adc(R3, R4)
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        // The add into R5 clears the carry left by the add before it
+        machine.step_n(6)?;
+        assert_eq!(machine.registers[R6 as usize], 0x0000);
+        assert!(!machine.flags.carry);
+
+        // And sets it again when it carries out itself
+        machine.step()?;
+        assert_eq!(machine.registers[R5 as usize], 0x0000);
+        assert!(machine.flags.carry);
+        machine.step()?;
+        assert_eq!(machine.registers[ACC as usize], 0x0001);
+        Ok(())
+    }
+
+    #[test]
+    fn trap_writes_to_null_when_asked() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .mov_reg_mem(R1, Ptr(0x0000))
+            .mov_reg_mem(R1, Ptr(0x0000))
+            .build()?;
+        machine.load_program(Ptr(0x0100), &program)?;
+        machine.registers[IP as usize] = 0x0100;
+
+        machine.step()?;
+        machine.trap_null_writes = true;
+        assert_eq!(machine.step(), Err(MachineError::NullWrite));
+        assert_eq!(machine.set8(Ptr(0x0001), 0xAB), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn encode_single_instructions() {
+        use crate::{Operand, OperandKind};
+
+        let bytes = encode(MoveLitToReg, &[Operand::Lit16(0x1234), Operand::Reg(R1)]).unwrap();
+        assert_eq!(bytes[..], [0x10, 0x12, 0x34, 0x04]);
+
+        let bytes = encode(
+            Switch,
+            &[
+                Operand::Reg(R2),
+                Operand::Addr(Ptr(0x0200)),
+                Operand::Lit16(3),
+                Operand::Addr(Ptr(0x0300)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(bytes.len(), Switch.encoded_len());
+
+        assert_eq!(
+            encode(PushReg, &[]),
+            Err(AsmError::OperandCount {
+                expected: 1,
+                found: 0
+            })
+        );
+        assert_eq!(
+            encode(MoveLitToReg, &[Operand::Reg(R1), Operand::Reg(R1)]),
+            Err(AsmError::OperandKind {
+                index: 0,
+                expected: OperandKind::Lit16
+            })
+        );
+    }
+
+    #[test]
+    fn tail_call_without_growing_the_stack() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let main = ProgramBuilder::<16>::new()
+            .call_lit(Ptr(0x3000))
+            .hlt()
+            .build()?;
+        let first = ProgramBuilder::<16>::new()
+            .push_lit(0x1111)
+            .tail_call(Ptr(0x3100))
+            .build()?;
+        let second = ProgramBuilder::<16>::new()
+            .push_lit(0x2222)
+            .tail_call(Ptr(0x3200))
+            .build()?;
+        let last = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x00C0, ACC)
+            .ret()
+            .build()?;
+        machine.load_program(Ptr(0), &main)?;
+        machine.load_program(Ptr(0x3000), &first)?;
+        machine.load_program(Ptr(0x3100), &second)?;
+        machine.load_program(Ptr(0x3200), &last)?;
+
+        machine.step()?;
+        let called_sp = machine.registers[SP as usize];
+        let called_fp = machine.registers[FP as usize];
+
+        machine.step_n(4)?;
+        assert_eq!(machine.registers[IP as usize], 0x3200);
+        assert_eq!(machine.registers[SP as usize], called_sp);
+        assert_eq!(machine.registers[FP as usize], called_fp);
+
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[IP as usize], 0x0003);
+        assert_eq!(machine.registers[ACC as usize], 0x00C0);
+        assert_eq!(
+            machine.registers[SP as usize],
+            Machine::<DEFAULT_MEMORY_LENGTH>::stack_base().0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn report_the_first_mismatched_byte() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+        machine.step_n(15)?;
+
+        assert_eq!(machine.assert_region(Ptr(0x0100), &[0x00, 0x03]), Ok(()));
+        assert_eq!(
+            machine.assert_region(Ptr(0x0100), &[0x00, 0x04]),
+            Err((Ptr(0x0101), 0x04, 0x03))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn count_down_until_acc_is_zero() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0x0003, ACC)
+            .mov_lit_reg(0xFFFF, R1)
+            // Loop at 0x0008, adding -1 until ACC reaches zero
+            .add(ACC, R1)
+            .jnz(Ptr(0x0008))
+            .jz(Ptr(0x0012))
+            .nop()
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        assert_eq!(machine.run_with_limit(100, false)?, RunOutcome::Halted(10));
+        assert_eq!(machine.registers[ACC as usize], 0x0000);
+        assert_eq!(
+            machine.instruction_histogram()[u8::from(AddRegReg) as usize],
+            3
+        );
+        assert_eq!(machine.instruction_histogram()[u8::from(Nop) as usize], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn shrink_stack_headroom_with_each_push() -> Result<(), MachineError> {
+        let mut machine = Machine::<512>::new();
+        machine.stack_limit = Ptr(0x0100);
+        let mut free = machine.stack_free();
+        // The limit is the lowest address the stack may write, and the first push writes
+        // the word at SP, so both ends count
+        assert_eq!(free, Machine::<512>::stack_base().0 + 2 - 0x0100);
+
+        for value in 0..4 {
+            machine.push(value)?;
+            assert_eq!(machine.stack_free(), free - 2);
+            free = machine.stack_free();
+        }
+        machine.pop()?;
+        assert_eq!(machine.stack_free(), free + 2);
+        Ok(())
+    }
+
+    #[test]
+    fn push_exactly_as_many_words_as_the_stack_has_free() -> Result<(), MachineError> {
+        for direction in [StackDirection::Down, StackDirection::Up] {
+            let mut machine = Machine::<512>::new();
+            machine.set_stack(direction, Ptr(0x0100))?;
+            machine.stack_limit = match direction {
+                StackDirection::Down => Ptr(0x00F0),
+                StackDirection::Up => Ptr(0x0110),
+            };
+            let expected = match direction {
+                StackDirection::Down => 0x12,
+                StackDirection::Up => 0x10,
+            };
+            assert_eq!(machine.stack_free(), expected);
+
+            for value in 0..machine.stack_free() / 2 {
+                machine.push(value)?;
+            }
+            assert_eq!(machine.stack_free(), 0);
+            assert_eq!(machine.push(0xFFFF), Err(MachineError::StackOverflow));
+            assert_eq!(machine.push8(0xFF), Err(MachineError::StackOverflow));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn keep_the_remainder_of_divisions() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(17, R1)
+            .mov_lit_reg(5, R2)
+            .div(R1, R2)
+            .div(R1, R3)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step_n(3)?;
+        assert_eq!(machine.registers[ACC as usize], 3);
+        assert_eq!(machine.registers[ACC2 as usize], 2);
+        assert_eq!(machine.step(), Err(MachineError::DivideByZero));
+        Ok(())
+    }
+
+    #[test]
+    fn run_one_off_programs() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x0042, R1)
+            .add(R1, R1)
+            .hlt()
+            .build()?;
+        let machine = run_program::<256>(&program)?;
+
+        assert!(machine.halted);
+        assert_eq!(machine.registers[R1 as usize], 0x0042);
+        assert_eq!(machine.registers[ACC as usize], 0x0084);
+        assert_eq!(machine.registers[IP as usize], 0x0008);
+
+        let endless = ProgramBuilder::<16>::new().jmp_rel(-3).build()?;
+        assert_eq!(
+            run_program::<256>(&endless).map(|machine| machine.halted),
+            Err(MachineError::StepLimitReached)
+        );
+
+        let unterminated = [Nop.into(); 256];
+        assert_eq!(
+            run_program::<256>(&unterminated).map(|machine| machine.halted),
+            Err(MachineError::OutOfBounds(Ptr(0x0100)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn push_and_pop_single_bytes() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .push_lit8(0xAB)
+            .mov_lit_reg(0x12CD, R1)
+            .push_reg8(R1)
+            .pop8(R2)
+            .pop8(R3)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        let sp = machine.registers[SP as usize];
+
+        machine.step()?;
+        assert_eq!(machine.registers[SP as usize], sp - 1);
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[SP as usize], sp - 2);
+        assert_eq!(machine.stack_words().next(), Some(0xCDAB));
+
+        machine.step()?;
+        assert_eq!(machine.registers[SP as usize], sp - 1);
+        assert_eq!(machine.registers[R2 as usize], 0x00CD);
+        assert_eq!(
+            machine.push_state(),
+            Err(MachineError::MisalignedStackPointer(sp - 1))
+        );
+        machine.step()?;
+        assert_eq!(machine.registers[SP as usize], sp);
+        assert_eq!(machine.registers[R3 as usize], 0x00AB);
+        Ok(())
+    }
+
+    #[test]
+    fn register_handlers_in_the_vector_table() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.set_handler(3, Ptr(0x4000))?;
+
+        assert_eq!(machine.get_handler(3)?, Ptr(0x4000));
+        assert_eq!(machine.get16(IVT_BASE + 6u16), 0x4000);
+        assert_eq!(
+            machine.set_handler(16, Ptr(0x4000)),
+            Err(MachineError::InvalidInterrupt(16))
+        );
+        assert_eq!(
+            machine.get_handler(16),
+            Err(MachineError::InvalidInterrupt(16))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn repeat_random_sequences_from_a_seed() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.map_device(Ptr(0xF010), Device::random(1))?;
+        let program = ProgramBuilder::<64>::new()
+            .mov_lit_reg(0x1234, R1)
+            .mov_reg_mem(R1, Ptr(0xF010))
+            .mov_mem_reg(Ptr(0xF010), R2)
+            .mov_mem_reg(Ptr(0xF010), R3)
+            .mov_mem_reg(Ptr(0xF010), R4)
+            .mov_reg_mem(R1, Ptr(0xF010))
+            .mov_mem_reg(Ptr(0xF010), R5)
+            .mov_mem_reg(Ptr(0xF010), R6)
+            .mov_mem_reg(Ptr(0xF010), R7)
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.step_n(10)?;
+
+        let first = &machine.registers[R2 as usize..=R4 as usize];
+        let second = &machine.registers[R5 as usize..=R7 as usize];
+        assert_eq!(first, second);
+        assert!(first[0] != first[1] && first[1] != first[2]);
+        Ok(())
+    }
+
+    #[test]
+    fn name_every_register() {
+        assert_eq!(ACC.name(), "ACC");
+        assert_eq!(ACC2.name(), "ACC2");
+
+        let machine = Machine::<256>::new();
+        let debug = format!("{machine:?}");
+        for id in 0..REGISTER_COUNT {
+            let register = crate::Registers::try_from(id).unwrap();
+            assert_eq!(register.name(), format!("{register:?}"));
+            assert!(debug.contains(&format!(" {}: ", register.name())));
+        }
+    }
+
+    #[test]
+    fn format_registers_at_their_widest() {
+        let mut machine = Machine::<256, 2>::new();
+        machine.registers = [0xFFFF; REGISTER_COUNT as usize];
+        machine.extra_registers = [0xFFFF; 2];
+
+        let debug = format!("{machine:?}");
+        assert_eq!(debug.matches("0xFFFF").count(), REGISTER_COUNT as usize + 2);
+        assert!(debug.contains("extra_registers: [\"0xFFFF\", \"0xFFFF\"]"));
+        assert!(!debug.contains("R9"));
+    }
+
+    #[test]
+    fn step_back_to_the_initial_state() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<32>::new()
+            .mov_lit_reg(0x1234, R1)
+            .push_reg(R1)
+            .mov_reg_mem(R1, Ptr(0x0080))
+            .hlt()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.set_journaling(true);
+        let initial = machine.clone();
+
+        machine.step_n(3)?;
+        assert_eq!(machine.registers[R1 as usize], 0x1234);
+        assert_eq!(machine.get16(Ptr(0x0080)), 0x1234);
+
+        for _ in 0..3 {
+            assert!(machine.step_back());
+        }
+        assert!(!machine.step_back());
+        assert_eq!(machine.registers, initial.registers);
+        assert_eq!(machine.memory, initial.memory);
+        assert_eq!(machine.flags, initial.flags);
+        assert_eq!(machine.stack_frame_size, initial.stack_frame_size);
+        assert_eq!(format!("{machine:?}"), format!("{initial:?}"));
+        assert_eq!(
+            machine.instruction_histogram(),
+            initial.instruction_histogram()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn move_results_out_of_acc() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x0102, R1)
+            .mov_lit_reg(0x0304, R2)
+            .add(R1, R2)
+            .mov_acc_reg(R3)
+            .hlt()
+            .build()?;
+        let machine = run_program::<256>(&program)?;
+
+        assert_eq!(machine.registers[R3 as usize], 0x0406);
+        assert_eq!(program.len(), 4 + 4 + 3 + 2 + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn fill_memory_with_a_poison_pattern() -> Result<(), MachineError> {
+        let mut machine = Machine::<256>::new_filled(0xCC);
+        machine.set16(Ptr(0x0010), 0x1234)?;
+
+        assert_eq!(machine.get16(Ptr(0x0010)), 0x1234);
+        assert!(machine.assert_region(Ptr(0x0012), &[0xCC; 16]).is_ok());
+        assert_eq!(machine.get(Ptr(0x00FF)), 0xCC);
+        assert_eq!(Machine::<256>::new().get(Ptr(0x00FF)), 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn pop_into_acc() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<8>::new()
+            .push_lit(0x4321)
+            .pop_acc()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step()?;
+        let sp = machine.registers[SP as usize];
+        machine.step()?;
+        assert_eq!(machine.registers[ACC as usize], 0x4321);
+        assert_eq!(machine.registers[SP as usize], sp + 2);
+        Ok(())
+    }
+
+    #[test]
+    fn clamp_windows_to_the_end_of_memory() {
+        let mut machine = Machine::<256>::new();
+        machine.memory[0xFF] = 0xAB;
+
+        let window = machine.get_window_clamped(Ptr(0x00F8), 32);
+        assert_eq!(window.data().len(), 8);
+        assert_eq!(window.data()[7], 0xAB);
+
+        let window = machine.get_window_clamped(Ptr(0x0000), 16);
+        assert_eq!(window.data().len(), 16);
+
+        let window = machine.get_window_clamped(Ptr(0x0200), 16);
+        assert!(window.data().is_empty());
+    }
+
+    #[test]
+    fn return_to_exactly_the_state_before_a_call() -> Result<(), MachineError> {
+        let subroutine = ProgramBuilder::<4>::new().ret().build()?;
+        let program = ProgramBuilder::<16>::new()
+            .push_lit(0xBEEF)
+            .call_lit(Ptr(0x0080))
+            .hlt()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(Ptr(0x0080), &subroutine)?;
+        machine.step()?;
+        assert!(machine.frame_consistent());
+        let before = machine.clone();
+
+        machine.step_n(2)?;
+        assert_eq!(machine.registers[IP as usize], 0x0006);
+        machine.registers[IP as usize] = before.registers[IP as usize];
+        assert_eq!(machine.registers, before.registers);
+        assert_eq!(machine.stack_frame_size, before.stack_frame_size);
+        assert!(machine.frame_consistent());
+        // Only the memory the call frame occupied below SP may differ
+        let sp = before.registers[SP as usize] as usize;
+        assert_eq!(machine.memory[sp + 1..], before.memory[sp + 1..]);
+        assert_eq!(machine.memory[..sp - 32], before.memory[..sp - 32]);
+        Ok(())
+    }
+
+    #[test]
+    fn index_into_word_arrays() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .mov_mem_reg_indexed(R1, R2, -4, R3)
+            .hlt()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        for (i, value) in [0x1111, 0x2222, 0x3333, 0x4444].into_iter().enumerate() {
+            machine.set16(Ptr(0x0080 + i as u16 * 2), value)?;
+        }
+        // The base points two words past the array, which the displacement backs out
+        machine.registers[R1 as usize] = 0x0084;
+        machine.registers[R2 as usize] = 3;
+
+        machine.step()?;
+        assert_eq!(machine.registers[R3 as usize], 0x4444);
+        Ok(())
+    }
+
+    #[test]
+    fn report_the_memory_size() {
+        let small = Machine::<256>::new();
+        let large = Machine::<4096>::new();
+
+        assert_eq!(small.memory_len(), 256);
+        assert_eq!(large.memory_len(), 4096);
+        assert_eq!(Machine::<256>::max_addr(), Ptr(0x00FF));
+        assert_eq!(Machine::<4096>::max_addr(), Ptr(0x0FFF));
+    }
+
+    #[test]
+    fn log_the_pushes_and_pops_of_a_frame() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        stack_frame_program(&mut machine)?;
+        machine.set_stack_logging(true);
+
+        // Run through the subroutine's Ret
+        machine.step_n(12)?;
+
+        let mut sp = Machine::<DEFAULT_MEMORY_LENGTH>::stack_base().0;
+        let mut expected = Vec::new();
+        let pushes = [
+            // Values pushed by the caller, then the arg count recorded by CallLit
+            0x3333, 0x2222, 0x1111, 0x0000,
+            // R1 to R8, the return address and the frame size saved by CallLit
+            0x1234, 0, 0, 0x5678, 0, 0, 0, 0, 0x0014, 0x001C,
+            // Values pushed by the subroutine
+            0x0102, 0x0304, 0x0506,
+        ];
+        for value in pushes {
+            sp -= 2;
+            expected.push(StackOp::Push { value, sp });
+        }
+        // Ret skips the subroutine's values and frame size, then pops the rest back off
+        sp = 0xFFE2;
+        for value in [0x0014, 0, 0, 0, 0, 0x5678, 0, 0, 0x1234, 0x0000] {
+            sp += 2;
+            expected.push(StackOp::Pop { value, sp });
+        }
+        assert_eq!(machine.stack_log().as_slice(), expected.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn set_several_registers_at_once() -> Result<(), MachineError> {
+        let mut machine = Machine::<256>::new();
+        machine.set_registers(&[(R1, 0x1111), (R2, 0x2222), (R3, 0x3333), (R4, 0x4444)])?;
+
+        assert_eq!(machine.registers[R1 as usize], 0x1111);
+        assert_eq!(machine.registers[R2 as usize], 0x2222);
+        assert_eq!(machine.registers[R3 as usize], 0x3333);
+        assert_eq!(machine.registers[R4 as usize], 0x4444);
+        assert_eq!(
+            machine.set_registers(&[(R5, 0x5555), (SP, 0x0081)]),
+            Err(MachineError::MisalignedStackPointer(0x0081))
+        );
+        assert_eq!(machine.registers[R5 as usize], 0x5555);
+        Ok(())
+    }
+
+    #[test]
+    fn call_subroutines_without_pushing_an_arg_count() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .push_lit(0x1111)
+            .push_lit(0x2222)
+            .call_lit(Ptr(0x0080))
+            .mov_lit_reg(0x0080, R1)
+            .call_reg(R1)
+            .hlt()
+            .build()?;
+        let subroutine = ProgramBuilder::<4>::new().ret().build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(Ptr(0x0080), &subroutine)?;
+        machine.step_n(2)?;
+        let sp = machine.registers[SP as usize];
+        let fp = machine.registers[FP as usize];
+
+        for _ in 0..2 {
+            machine.step_n(2)?;
+            assert_eq!(machine.registers[SP as usize], sp);
+            assert_eq!(machine.registers[FP as usize], fp);
+            assert_eq!(machine.stack_frame_size, 4);
+            assert!(machine.stack_words().eq([0x2222, 0x1111]));
+            machine.step()?;
+        }
+        assert!(machine.halted);
+        Ok(())
+    }
+
+    #[test]
+    fn clean_up_args_pushed_for_a_call() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .push_lit(0x1111)
+            .push_lit(0x2222)
+            .call_lit_args(Ptr(0x0080), 2)
+            .call_lit_args(Ptr(0x0084), 0)
+            .hlt()
+            .build()?;
+        // Reads the last arg pushed, then returns. The Ret alone serves as a subroutine
+        // taking no args at 0x0084
+        let subroutine = ProgramBuilder::<8>::new()
+            .load_local(24, ACC)
+            .ret()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(Ptr(0x0080), &subroutine)?;
+        let sp = machine.registers[SP as usize];
+        let fp = machine.registers[FP as usize];
+
+        // Run the first call through to its return
+        machine.step_n(5)?;
+        assert_eq!(machine.registers[ACC as usize], 0x2222);
+        assert_eq!(machine.registers[SP as usize], sp);
+        assert_eq!(machine.registers[FP as usize], fp);
+        assert_eq!(machine.stack_frame_size, 0);
+
+        machine.step_n(3)?;
+        assert!(machine.halted);
+        assert_eq!(machine.registers[SP as usize], sp);
+        assert_eq!(machine.registers[FP as usize], fp);
+        Ok(())
+    }
+
+    #[test]
+    fn walk_nested_call_frames() -> Result<(), MachineError> {
+        let main = ProgramBuilder::<8>::new()
+            .call_lit_args(Ptr(0x0080), 0)
+            .hlt()
+            .build()?;
+        let outer = ProgramBuilder::<8>::new()
+            .call_lit_args(Ptr(0x00A0), 0)
+            .ret()
+            .build()?;
+        let inner = ProgramBuilder::<8>::new().hlt().build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &main)?;
+        machine.load_program(Ptr(0x0080), &outer)?;
+        machine.load_program(Ptr(0x00A0), &inner)?;
+        assert!(machine.frames().is_empty());
+
+        machine.step_n(10)?;
+        let frames = machine.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].fp, Ptr(machine.registers[FP as usize]));
+        assert_eq!(frames[0].return_addr, Ptr(0x0084));
+        assert_eq!(frames[1].return_addr, Ptr(0x0004));
+        assert!(frames[0].fp < frames[1].fp);
+        Ok(())
+    }
+
+    #[test]
+    fn grow_the_stack_upwards() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .push_lit(0x1111)
+            .push_lit(0x2222)
+            .push_lit(0x3333)
+            .call_lit_args(Ptr(0x0080), 1)
+            .hlt()
+            .build()?;
+        let subroutine = ProgramBuilder::<4>::new().ret().build()?;
+        let mut machine = Machine::<512>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(Ptr(0x0080), &subroutine)?;
+        machine.set_stack(StackDirection::Up, Ptr(0x0100))?;
+
+        machine.step_n(3)?;
+        assert_eq!(machine.registers[SP as usize], 0x0106);
+        assert_eq!(machine.get16(Ptr(0x0104)), 0x3333);
+        assert_eq!(
+            machine.stack_words().collect::<Vec<_>>(),
+            [0x3333, 0x2222, 0x1111]
+        );
+
+        // The call's frame sits above the args, and returning pops the arg passed
+        machine.step()?;
+        assert_eq!(machine.registers[FP as usize], 0x0106 + 2 + 16 + 2 + 2);
+        assert_eq!(machine.frames()[0].return_addr, Ptr(0x000D));
+        machine.step()?;
+        assert_eq!(machine.registers[SP as usize], 0x0104);
+        assert_eq!(machine.registers[FP as usize], 0x0100);
+        assert!(machine.frame_consistent());
+
+        assert_eq!(machine.pop()?, 0x2222);
+        assert_eq!(machine.pop()?, 0x1111);
+        assert_eq!(machine.registers[SP as usize], 0x0100);
+        Ok(())
+    }
+
+    #[test]
+    fn skip_over_data_in_code() -> Result<(), MachineError> {
+        let mut program = [0; 11];
+        program[..4].copy_from_slice(&[MoveLitToReg.into(), 0x12, 0x34, R1.into()]);
+        // A table which would decode as the start of a MoveLitToReg
+        program[4..7].copy_from_slice(&[0x10, 0xFF, 0x11]);
+        program[7..].copy_from_slice(&[MoveRegToReg.into(), R1.into(), R2.into(), Hlt.into()]);
+        let symbols = [(Ptr(0x0004), "table")];
+
+        let mut listing = String::new();
+        disassemble(&mut listing, &program, Ptr(0), 11, &symbols).unwrap();
+        assert!(!listing.contains("0x0007: MOVR"));
+
+        let mut listing = String::new();
+        let data = [Ptr(0x0004)..Ptr(0x0007)];
+        disassemble_with_data(&mut listing, &program, Ptr(0), 11, &symbols, &data).unwrap();
+        assert_eq!(
+            listing,
+            "0x0000: MOV 0x1234, R1\n\
+             table:\n\
+             0x0004: DB 0x10, 0xFF, 0x11\n\
+             0x0007: MOVR R1, R2\n\
+             0x000A: HLT\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn split_programs_into_basic_blocks() -> Result<(), MachineError> {
+        let mut machine = Machine::<256>::new();
+        counter_program(&mut machine)?;
+
+        // The whole counter program is one loop body which branches back to its start
+        let blocks = basic_blocks(&machine.memory, Ptr(0), 20);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!((blocks[0].start, blocks[0].end), (Ptr(0x0000), Ptr(0x0014)));
+        assert_eq!(blocks[0].successors, [Ptr(0x0000), Ptr(0x0014)]);
+
+        // Starting part way in, the branch target is outside the stream
+        let blocks = basic_blocks(&machine.memory, Ptr(0x0004), 16);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].start, Ptr(0x0004));
+
+        let program = ProgramBuilder::<16>::new()
+            .call_lit(Ptr(0x0007))
+            .jmp_rel(-6)
+            .ret()
+            .build()?;
+        let blocks = basic_blocks(&program, Ptr(0), program.len());
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].successors, [Ptr(0x0007), Ptr(0x0003)]);
+        assert_eq!(blocks[1].successors, [Ptr(0x0000)]);
+        assert_eq!((blocks[2].start, blocks[2].end), (Ptr(0x0006), Ptr(0x0007)));
+        assert!(blocks[2].successors.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn execute_instructions_from_raw_bytes() -> Result<(), MachineError> {
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &[Hlt.into(); 4])?;
+
+        machine.execute_bytes(&[0x10, 0x12, 0x34, 0x04])?;
+        assert_eq!(machine.registers[R1 as usize], 0x1234);
+        assert_eq!(machine.registers[IP as usize], 0x0000);
+        assert!(machine.assert_region(Ptr(0), &[Hlt.into(); 4]).is_ok());
+
+        machine.execute_bytes(&[JmpRel.into(), 0x00, 0x10])?;
+        assert_eq!(machine.registers[IP as usize], 0x0013);
+
+        assert_eq!(
+            machine.execute_bytes(&[0x10, 0x12, 0x34, 0x04, 0, 0, 0, 0, 0]),
+            Err(MachineError::ProgramTooLarge)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn recover_from_faults_in_a_handler() -> Result<(), MachineError> {
+        fn skip_bad_bytes(machine: &mut Machine<256>, fault: &MachineError) -> FaultAction {
+            match fault {
+                MachineError::InvalidInstruction { at, .. } => {
+                    machine.registers[IP as usize] = at.0 + 1;
+                    FaultAction::Retry
+                }
+                MachineError::DivideByZero => FaultAction::Skip,
+                _ => FaultAction::Propagate,
+            }
+        }
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1234, R1)
+            .div(R1, R2)
+            .mov_acc_reg(R3)
+            .hlt()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        // A stray byte ahead of the program which doesn't decode
+        machine.load_program(Ptr(0), &[0xEE])?;
+        machine.load_program(Ptr(1), &program)?;
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::InvalidInstruction {
+                opcode: 0xEE,
+                at: Ptr(0)
+            })
+        );
+
+        machine.set_fault_handler(Some(skip_bad_bytes));
+        machine.step_n(4)?;
+        assert!(machine.halted);
+        assert_eq!(machine.registers[R1 as usize], 0x1234);
+
+        machine.registers[IP as usize] = 0x0040;
+        machine.halted = false;
+        machine.memory[0x0040] = MoveRegToReg.into();
+        machine.memory[0x0041] = 0x0D;
+        assert_eq!(machine.step(), Err(MachineError::InvalidRegister(0x0D)));
+        Ok(())
+    }
+
+    #[test]
+    fn stop_retrying_faults_which_keep_happening() -> Result<(), MachineError> {
+        fn always_retry(machine: &mut Machine<256>, _: &MachineError) -> FaultAction {
+            // Leaves the bad byte as it is, so every retry faults again
+            machine.registers[R8 as usize] += 1;
+            FaultAction::Retry
+        }
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &[0xEE])?;
+        machine.set_fault_handler(Some(always_retry));
+
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::InvalidInstruction {
+                opcode: 0xEE,
+                at: Ptr(0)
+            })
+        );
+        assert_eq!(
+            machine.registers[R8 as usize] as usize,
+            FAULT_RETRY_LIMIT + 1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn retry_whole_instructions_after_fixing_an_operand() -> Result<(), MachineError> {
+        fn fix_register(machine: &mut Machine<256>, fault: &MachineError) -> FaultAction {
+            let at = machine.registers[IP as usize];
+            match fault {
+                MachineError::InvalidRegister(_) => {
+                    machine.memory[at as usize + 2] = R2.into();
+                    FaultAction::Retry
+                }
+                _ => FaultAction::Propagate,
+            }
+        }
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0x0010), &[MoveRegToReg.into(), R1.into(), 0x0D])?;
+        machine.registers[IP as usize] = 0x0010;
+        machine.registers[R1 as usize] = 0x4321;
+        machine.set_fault_handler(Some(fix_register));
+
+        machine.step()?;
+        assert_eq!(machine.registers[R2 as usize], 0x4321);
+        assert_eq!(machine.registers[IP as usize], 0x0013);
+        Ok(())
+    }
+
+    #[test]
+    fn only_skip_faults_raised_by_instructions() -> Result<(), MachineError> {
+        fn always_skip(_: &mut Machine<256>, _: &MachineError) -> FaultAction {
+            FaultAction::Skip
+        }
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &[Nop.into(), Hlt.into()])?;
+        machine.set_fault_handler(Some(always_skip));
+        // Leave the stack no room to save the state for the interrupt
+        machine.stack_limit = Ptr(0x00FF);
+        machine.raise_interrupt(0)?;
+
+        assert_eq!(machine.step(), Err(MachineError::StackOverflow));
+        assert_eq!(machine.registers[IP as usize], 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn sign_extend_signed_byte_loads() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .mov_mem_reg_byte_signed(Ptr(0x0080), R1)
+            .mov_mem_reg_byte(Ptr(0x0080), R2)
+            .mov_mem_reg_byte_signed(Ptr(0x0081), R3)
+            .hlt()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(Ptr(0x0080), &[0x80, 0x7F])?;
+        machine.step_n(4)?;
+
+        assert_eq!(machine.registers[R1 as usize], 0xFF80);
+        assert_eq!(machine.registers[R2 as usize], 0x0080);
+        assert_eq!(machine.registers[R3 as usize], 0x007F);
+        Ok(())
+    }
+
+    #[test]
+    fn measure_instructions_in_place() -> Result<(), MachineError> {
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1234, R1)
+            .add(R1, R2)
+            .hlt()
+            .build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.memory[0x0080] = 0xEE;
+
+        assert_eq!(machine.instruction_len_at(Ptr(0))?, 4);
+        assert_eq!(machine.instruction_len_at(Ptr(4))?, 3);
+        assert_eq!(machine.instruction_len_at(Ptr(7))?, 1);
+        assert_eq!(
+            machine.instruction_len_at(Ptr(0x0080)),
+            Err(MachineError::InvalidInstruction {
+                opcode: 0xEE,
+                at: Ptr(0x0080)
+            })
+        );
+        assert_eq!(
+            machine.instruction_len_at(Ptr(0x0100)),
+            Err(MachineError::OutOfBounds(Ptr(0x0100)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn map_instructions_to_and_from_mnemonics() {
+        let instructions = (0..=u8::MAX)
+            .filter_map(|opcode| crate::Instructions::try_from(opcode).ok())
+            .filter(|instruction| *instruction != Reserved);
+        for instruction in instructions {
+            assert_eq!(
+                crate::Instructions::from_mnemonic(instruction.mnemonic()),
+                Some(instruction)
+            );
+        }
+        assert_eq!(MoveLitToReg.mnemonic(), "MOV");
+        assert_eq!(crate::Instructions::from_mnemonic("RSVD"), None);
+        assert_eq!(crate::Instructions::from_mnemonic("mov"), None);
+        assert_eq!(crate::Instructions::from_mnemonic("MoveLitToReg"), None);
+        assert_eq!(crate::Instructions::from_mnemonic(""), None);
+    }
+
+    #[test]
+    fn count_memory_accesses_per_page() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+        machine.set_access_stats(true);
+        assert_eq!(machine.access_stats().len(), 256);
+
+        // Three trips around the loop before ACC reaches 3
+        machine.step_n(15)?;
+        let stats = machine.access_stats();
+        assert_eq!(stats[0x0100 / crate::PAGE_LENGTH].reads, 3);
+        assert_eq!(stats[0x0100 / crate::PAGE_LENGTH].writes, 3);
+        // Instruction fetches from the first page aren't counted
+        assert_eq!(stats[0], Default::default());
+
+        machine.set_access_stats(false);
+        assert!(machine.access_stats().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn halt_with_an_exit_code() -> Result<(), MachineError> {
+        let mut machine = Machine::<256>::new();
+        assert_eq!(machine.exit_code, None);
+
+        let program = ProgramBuilder::<8>::new()
+            .mov_lit_reg(0x0102, ACC)
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.step_n(2)?;
+        assert!(machine.halted);
+        assert_eq!(machine.exit_code, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn run_until_a_condition_holds() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+        // Loop until the counter reaches 0x10 rather than 3
+        machine.set16(Ptr(0x0010), 0x0010)?;
+
+        let counter_is_5 =
+            |machine: &Machine<DEFAULT_MEMORY_LENGTH>| machine.get16(Ptr(0x0100)) == 5;
+        assert_eq!(
+            machine.run_until(counter_is_5, 1000)?,
+            RunOutcome::Reached(24)
+        );
+        assert_eq!(machine.get16(Ptr(0x0100)), 5);
+        assert_eq!(machine.registers[IP as usize], 0x000F);
+        assert_eq!(
+            machine.run_until(counter_is_5, 1000)?,
+            RunOutcome::Reached(0)
+        );
+
+        let counter_is_0 =
+            |machine: &Machine<DEFAULT_MEMORY_LENGTH>| machine.get16(Ptr(0x0100)) == 0;
+        assert_eq!(machine.run_until(counter_is_0, 10)?, RunOutcome::StepLimit);
+        Ok(())
+    }
+
+    #[test]
+    fn dump_memory_as_a_window_formats_it() -> Result<(), MachineError> {
+        use core::fmt::Write;
+
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        let mut dumped = heapless::String::<512>::new();
+        machine.dump(&mut dumped, Ptr(0), 36).unwrap();
+        let mut formatted = heapless::String::<512>::new();
+        write!(formatted, "{:?}", machine.get_window(Ptr(0), 36)).unwrap();
+        assert_eq!(dumped, formatted);
+
+        let mut tail = heapless::String::<64>::new();
+        machine.dump(&mut tail, Ptr(0xFFFE), 8).unwrap();
+        assert!(tail.starts_with("[0xFFFE] "));
+        assert_eq!(tail.matches(" _x__").count(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn decode_the_operands_of_an_instruction() -> Result<(), MachineError> {
+        use crate::Operand;
+
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .nop()
+            .mov_lit_reg(0x1234, R1)
+            .build()?;
+        machine.load_program(Ptr(0x0100), &program)?;
+
+        let (instruction, operands) = machine.decode_at(Ptr(0x0101))?;
+        assert_eq!(instruction, MoveLitToReg);
+        assert_eq!(operands[..], [Operand::Lit16(0x1234), Operand::Reg(R1)]);
+
+        machine.set8(Ptr(0x0200), PushReg.into())?;
+        machine.set8(Ptr(0x0201), 0xEE)?;
+        assert_eq!(
+            machine.decode_at(Ptr(0x0200)),
+            Err(MachineError::InvalidRegister(0xEE))
+        );
+        assert_eq!(
+            PushReg.decode_operands(&[]),
+            Err(MachineError::BufferTooSmall)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_to_tear_down_more_of_a_frame_than_was_built() -> Result<(), MachineError> {
+        // Claims three args were pushed for the call when only one was
+        let program = ProgramBuilder::<16>::new()
+            .push_lit(0x1111)
+            .call_lit_args(Ptr(0x0080), 3)
+            .hlt()
+            .build()?;
+        let subroutine = ProgramBuilder::<8>::new().ret().build()?;
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0), &program)?;
+        machine.load_program(Ptr(0x0080), &subroutine)?;
+
+        machine.step_n(2)?;
+        // Unwinding the args used to wrap the caller's frame size around to 0xFFFE
+        assert_eq!(machine.step(), Err(MachineError::FrameCorruption));
+
+        let mut machine = Machine::<256>::new();
+        machine.push(0x1111)?;
+        assert_eq!(machine.pop(), Ok(0x1111));
+        assert_eq!(machine.pop(), Err(MachineError::FrameCorruption));
+        assert_eq!(machine.stack_frame_size, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn build_machines_of_any_size() -> Result<(), MachineError> {
+        fn count_to_3<const MEMORY: usize>() -> Result<Machine<MEMORY>, MachineError> {
+            let mut machine = Machine::<MEMORY>::new();
+            counter_program(&mut machine)?;
+            machine.step_n(15)?;
+            Ok(machine)
+        }
+
+        let small = count_to_3::<512>()?;
+        let medium = count_to_3::<4096>()?;
+        let large = count_to_3::<DEFAULT_MEMORY_LENGTH>()?;
+        assert_eq!(small.memory.len(), backing_len(512));
+        assert_eq!(medium.memory.len(), backing_len(4096));
+        assert_eq!(large.memory.len(), backing_len(DEFAULT_MEMORY_LENGTH));
+        for counter in [small.get16(Ptr(0x0100)), medium.get16(Ptr(0x0100))] {
+            assert_eq!(counter, large.get16(Ptr(0x0100)));
+        }
+        assert_eq!(large.get16(Ptr(0x0100)), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn load_an_address_and_read_through_it() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .lea(Ptr(0x0400), R1)
+            .mov_mem_reg_indexed(R1, R2, 2, R3)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.set16(Ptr(0x0402), 0xBEEF)?;
+
+        machine.step()?;
+        assert_eq!(machine.registers[R1 as usize], 0x0400);
+        machine.step()?;
+        assert_eq!(machine.registers[R3 as usize], 0xBEEF);
+        Ok(())
+    }
+
+    #[test]
+    fn decode_an_instruction_before_executing_it() -> Result<(), MachineError> {
+        use crate::Operand;
+
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1234, R1)
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        let decoded = machine.decode()?;
+        assert_eq!(decoded.addr, Ptr(0));
+        assert_eq!(decoded.instruction, MoveLitToReg);
+        assert_eq!(
+            decoded.operands[..],
+            [Operand::Lit16(0x1234), Operand::Reg(R1)]
+        );
+        assert_eq!(machine.registers[IP as usize], 0x0004);
+        assert_eq!(machine.registers[R1 as usize], 0);
+
+        machine.execute_decoded(decoded)?;
+        assert_eq!(machine.registers[R1 as usize], 0x1234);
+        assert_eq!(machine.registers[IP as usize], 0x0004);
+        assert_eq!(machine.decode()?.instruction, Hlt);
+        Ok(())
+    }
+
+    #[test]
+    fn execute_decoded_instructions_without_writing_them_into_memory() -> Result<(), MachineError> {
+        let mut machine = Machine::<256>::new();
+        machine.set_coverage(true);
+        machine.registers[R1 as usize] = 0xABCD;
+        let program = ProgramBuilder::<16>::new()
+            .mov_reg_mem(R1, Ptr(0x0000))
+            .hlt()
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        // The instruction overwrites its own first bytes, which must stick
+        let decoded = machine.decode()?;
+        machine.execute_decoded(decoded)?;
+        assert!(machine.assert_region(Ptr(0), &[0xAB, 0xCD]).is_ok());
+        assert_eq!(machine.instruction_histogram()[MoveRegToMem as usize], 1);
+        assert_eq!(machine.coverage()[0] & 1, 1);
+
+        // Running bytes from outside memory doesn't touch the protected code at the IP
+        machine.protect(Ptr(0)..Ptr(8))?;
+        machine.registers[IP as usize] = 0;
+        machine.execute_bytes(&[MoveLitToReg.into(), 0x12, 0x34, R2.into()])?;
+        assert_eq!(machine.registers[R2 as usize], 0x1234);
+        assert!(machine.assert_region(Ptr(0), &[0xAB, 0xCD]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_register_ids_past_the_last_register() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.registers[ACC2 as usize] = 0x2222;
+        assert_eq!(machine.reg_checked(ACC2.into()), Ok(0x2222));
+        assert_eq!(
+            machine.reg_checked(REGISTER_COUNT),
+            Err(MachineError::InvalidRegister(REGISTER_COUNT))
+        );
+        assert_eq!(
+            machine.reg_checked(0xFF),
+            Err(MachineError::InvalidRegister(0xFF))
+        );
+
+        machine.load_program(Ptr(0), &[PushReg.into(), REGISTER_COUNT])?;
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::InvalidRegister(REGISTER_COUNT))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn display_instructions_by_mnemonic() {
+        assert_eq!(format!("{}", AddRegReg), "ADD");
+        assert_eq!(format!("{}", MoveLitToReg), "MOV");
+        assert_ne!(format!("{}", MoveLitToReg), format!("{:?}", MoveLitToReg));
+        assert_eq!(format!("{:<8}|", Hlt), "HLT     |");
+        for opcode in 0..=u8::MAX {
+            if let Ok(instruction) = crate::Instructions::try_from(opcode) {
+                assert_eq!(format!("{instruction}"), instruction.mnemonic());
+            }
+        }
+    }
+
+    #[test]
+    fn cover_the_opcodes_each_program_runs() -> Result<(), MachineError> {
+        let covered = |coverage: &[u8], addr: usize| coverage[addr / 8] & 1 << (addr % 8) != 0;
+
+        let mut straight = Machine::default();
+        straight.set_coverage(true);
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(1, R1)
+            .nop()
+            .hlt()
+            .build()?;
+        straight.load_program(Ptr(0), &program)?;
+        straight.step_n(10)?;
+
+        let mut jumping = Machine::default();
+        jumping.set_coverage(true);
+        let program = ProgramBuilder::<16>::new().jmp_rel(1).nop().hlt().build()?;
+        jumping.load_program(Ptr(0), &program)?;
+        jumping.step_n(10)?;
+
+        let straight = straight.coverage();
+        let jumping = jumping.coverage();
+        assert_ne!(straight, jumping);
+        // Operand bytes aren't covered, only the opcodes which were fetched
+        for addr in [0x0000, 0x0004, 0x0005] {
+            assert!(covered(straight, addr));
+        }
+        assert!(!covered(straight, 0x0001));
+        for addr in [0x0000, 0x0004] {
+            assert!(covered(jumping, addr));
+        }
+        assert!(!covered(jumping, 0x0003));
+        assert_eq!(
+            straight.iter().map(|byte| byte.count_ones()).sum::<u32>(),
+            3
+        );
+        assert_eq!(jumping.iter().map(|byte| byte.count_ones()).sum::<u32>(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_to_move_words_through_an_odd_stack_pointer() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.push(0x1234)?;
+        machine.push(0x5678)?;
+        // Knock SP odd from outside, past the alignment check set_reg makes
+        let sp = machine.registers[SP as usize] + 1;
+        machine.registers[SP as usize] = sp;
+        let program = ProgramBuilder::<16>::new().pop(R1).build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::MisalignedStackPointer(sp))
+        );
+        assert_eq!(machine.registers[SP as usize], sp);
+        assert_eq!(machine.registers[R1 as usize], 0);
+        assert_eq!(
+            machine.push(0),
+            Err(MachineError::MisalignedStackPointer(sp))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_and_run_a_container_of_segments() -> Result<(), LoadError> {
+        let main = ProgramBuilder::<16>::new()
+            .call_lit_args(Ptr(0x3000), 0)
+            .hlt()
+            .build()?;
+        let subroutine = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x0042, ACC)
+            .ret()
+            .build()?;
+        let container = build_container::<64>(
+            Ptr(0x0010),
+            &[(Ptr(0x0010), &main), (Ptr(0x3000), &subroutine)],
+        )?;
+
+        let mut machine = Machine::default();
+        let entry = machine.load_container(&container)?;
+        assert_eq!(entry, Ptr(0x0010));
+        machine.registers[IP as usize] = entry.0;
+        machine.step_n(10)?;
+        assert!(machine.halted);
+        assert_eq!(machine.registers[ACC as usize], 0x0042);
+
+        assert_eq!(
+            machine.load_container(&container[..10]),
+            Err(LoadError::TruncatedContainer)
+        );
+        let mut patched = container.clone();
+        patched[0] = b'X';
+        assert_eq!(machine.load_container(&patched), Err(LoadError::BadMagic));
+        patched = container.clone();
+        patched[4] = 2;
+        assert_eq!(
+            machine.load_container(&patched),
+            Err(LoadError::UnsupportedVersion(2))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn swap_registers_in_place() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.registers[R1 as usize] = 0x1234;
+        machine.registers[R2 as usize] = 0x5678;
+        let program = ProgramBuilder::<16>::new()
+            .swap_reg(R1, R2)
+            .swap_reg(R3, SP)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        let sp = machine.registers[SP as usize];
+
+        machine.step()?;
+        assert_eq!(machine.registers[R1 as usize], 0x5678);
+        assert_eq!(machine.registers[R2 as usize], 0x1234);
+
+        // An odd value can't go into SP, so R3 keeps its own as well
+        machine.registers[R3 as usize] = 0x0101;
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::MisalignedStackPointer(0x0101))
+        );
+        assert_eq!(machine.registers[R3 as usize], 0x0101);
+        assert_eq!(machine.registers[SP as usize], sp);
+        Ok(())
+    }
+
+    #[test]
+    fn report_the_length_of_each_disassembled_instruction() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+
+        let entries = disassemble_entries::<8>(&machine.memory, Ptr(0), 20, &[]).unwrap();
+        let lengths: std::vec::Vec<(Ptr, usize)> =
+            entries.iter().map(|(at, len, _)| (*at, *len)).collect();
+        assert_eq!(
+            lengths,
+            [
+                (Ptr(0x0000), 4),
+                (Ptr(0x0004), 4),
+                (Ptr(0x0008), 3),
+                (Ptr(0x000B), 4),
+                (Ptr(0x000F), 5),
+            ]
+        );
+        assert_eq!(entries[2].2, "0x0008: ADD R1, R2");
+
+        let first_two = disassemble_entries::<2>(&machine.memory, Ptr(0), 20, &[]).unwrap();
+        assert_eq!(first_two.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn read_null_terminated_strings() -> Result<(), MachineError> {
+        let mut machine = Machine::<256>::new();
+        machine.load_program(Ptr(0x0040), b"HELLO\0WORLD")?;
+        machine.load_program(Ptr(0x00FC), b"TAIL")?;
+
+        assert_eq!(machine.read_cstr::<16>(Ptr(0x0040), 64)?, b"HELLO");
+        assert_eq!(machine.read_cstr::<16>(Ptr(0x0040), 3)?, b"HEL");
+        assert_eq!(machine.read_cstr::<16>(Ptr(0x0045), 64)?, b"");
+        assert_eq!(
+            machine.read_cstr::<4>(Ptr(0x0040), 64),
+            Err(MachineError::BufferTooSmall)
+        );
+        assert_eq!(
+            machine.read_cstr::<16>(Ptr(0x00FC), 64),
+            Err(MachineError::OutOfBounds(Ptr(0x0100)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn preserve_acc_across_calls_when_callee_saved() -> Result<(), MachineError> {
+        let main = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1111, ACC)
+            .call_lit_args(Ptr(0x0080), 0)
+            .hlt()
+            .build()?;
+        let subroutine = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x2222, ACC)
+            .ret()
+            .build()?;
+        let run = |saving: AccSaving| -> Result<VMSize, MachineError> {
+            let mut machine = Machine::<256>::new();
+            machine.set_acc_saving(saving);
+            machine.load_program(Ptr(0), &main)?;
+            machine.load_program(Ptr(0x0080), &subroutine)?;
+            machine.step_n(10)?;
+            assert!(machine.halted);
+            assert!(machine.frame_consistent());
+            Ok(machine.registers[ACC as usize])
+        };
+
+        assert_eq!(run(AccSaving::CallerSaved)?, 0x2222);
+        assert_eq!(run(AccSaving::CalleeSaved)?, 0x1111);
+        Ok(())
+    }
+
+    #[test]
+    fn stream_events_for_each_step() -> Result<(), MachineError> {
+        use crate::{MachineEvent, RegisterChange};
+
+        let mut machine = Machine::default();
+        let program = ProgramBuilder::<16>::new()
+            .mov_lit_reg(0x1234, R1)
+            .push_reg(R1)
+            .mov_reg_mem(R1, Ptr(0x0200))
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+        machine.set_events(true);
+        machine.step_n(3)?;
+        let sp = machine.registers[SP as usize];
+
+        let events: std::vec::Vec<MachineEvent> = machine.drain_events().collect();
+        assert_eq!(
+            events[..3],
+            [
+                MachineEvent::InstructionExecuted {
+                    at: Ptr(0),
+                    instruction: MoveLitToReg
+                },
+                MachineEvent::RegisterChanged(RegisterChange {
+                    register: IP,
+                    old: 0x0000,
+                    new: 0x0004
+                }),
+                MachineEvent::RegisterChanged(RegisterChange {
+                    register: R1,
+                    old: 0x0000,
+                    new: 0x1234
+                }),
+            ]
+        );
+        assert!(events.contains(&MachineEvent::Stack(StackOp::Push { value: 0x1234, sp })));
+        assert!(events.contains(&MachineEvent::MemoryWritten(MemoryChange {
+            addr: Ptr(0x0201),
+            old: 0x00,
+            new: 0x34
+        })));
+        assert_eq!(machine.drain_events().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn negate_a_register_into_acc() -> Result<(), MachineError> {
+        let mut machine = Machine::default();
+        machine.registers[R1 as usize] = 0x0001;
+        machine.registers[R2 as usize] = 0x8000;
+        let program = ProgramBuilder::<16>::new()
+            .neg_reg(R1)
+            .neg_reg(R2)
+            .build()?;
+        machine.load_program(Ptr(0), &program)?;
+
+        machine.step()?;
+        assert_eq!(machine.registers[ACC as usize], 0xFFFF);
+        assert_eq!(machine.registers[R1 as usize], 0x0001);
+        assert!(machine.flags.negative);
+
+        // The most negative value has no positive counterpart and negates to itself
+        machine.step()?;
+        assert_eq!(machine.registers[ACC as usize], 0x8000);
+        assert!(machine.flags.overflow);
+        Ok(())
+    }
+
+    #[test]
+    fn patch_an_instruction_while_running() -> Result<(), MachineError> {
+        use crate::Operand;
+
+        let mut machine = Machine::default();
+        counter_program(&mut machine)?;
+        machine.step_n(5)?;
+        assert_eq!(machine.get16(Ptr(0x0100)), 1);
 
-        machine.set8(i.inc(), Ret.into());
+        // Skip the store on later passes by turning its opcode into a Nop
+        machine.patch(Ptr(0x000B), Nop, &[])?;
+        machine.registers[IP as usize] = 0x000B;
+        assert_eq!(machine.step_returning()?, Nop);
+        assert_eq!(machine.registers[IP as usize], 0x000C);
 
-        machine.set8(i.inc(), PushLit.into());
-        machine.set16(i.inc_by(2), 0x9999);
+        machine.patch(
+            Ptr(0x000B),
+            MoveRegToMem,
+            &[Operand::Reg(ACC), Operand::Addr(Ptr(0x0102))],
+        )?;
+        machine.registers[IP as usize] = 0x000B;
+        assert_eq!(machine.step_returning()?, MoveRegToMem);
+        assert_eq!(machine.get16(Ptr(0x0102)), machine.registers[ACC as usize]);
 
+        machine.protect(Ptr(0x0008)..Ptr(0x000C))?;
+        assert_eq!(
+            machine.patch(
+                Ptr(0x0008),
+                MoveLitToReg,
+                &[Operand::Lit16(7), Operand::Reg(R1)]
+            ),
+            Err(MachineError::WriteProtected(Ptr(0x0008)))
+        );
+        assert_eq!(machine.get(Ptr(0x0008)), AddRegReg.into());
+        assert_eq!(
+            machine.patch(Ptr(0x0020), PushReg, &[]),
+            Err(MachineError::InvalidOperands {
+                opcode: PushReg.into(),
+                at: Ptr(0x0020)
+            })
+        );
+        assert_eq!(
+            machine.patch(Ptr(0xFFFE), PushReg, &[Operand::Reg(R1)]),
+            Err(MachineError::OutOfBounds(Ptr(0xFFFE)))
+        );
+        Ok(())
     }
 
     #[test]
@@ -255,7 +3623,7 @@ mod should {
 
         print_machine_state(&machine, &[]);
 
-        counter_program(&mut machine);
+        counter_program(&mut machine).unwrap();
         
         // swap_registers_program(&mut machine);
 
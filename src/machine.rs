@@ -1,59 +1,318 @@
-use core::{fmt, fmt::Write, mem};
+use core::{fmt, fmt::Write, mem, ops::Range};
 
-use heapless::String;
+use heapless::{Deque, String, Vec};
 
 use crate::{
-    Instructions, Instructions::*, MachineError, MemoryWindow, Ptr, Registers, Registers::*,
-    VMSize, REGISTER_COUNT,
+    encode, AccSaving, AnnotatedWindow, DecodedInstruction, Device, FaultAction, Flags, FrameInfo,
+    FrameSize, Instructions, Instructions::*, MachineError, MachineEvent, MappedDevice,
+    MemoryChange, MemoryWindow, Operand, PageStats, Ptr, RegisterChange, Registers, Registers::*,
+    RunOutcome, StackDirection, StackOp, UndoRecord, VMSize, COVERAGE_LENGTH, DEVICE_COUNT,
+    EVENT_QUEUE_LENGTH, FAULT_RETRY_LIMIT, INTERRUPT_QUEUE_LENGTH, INTERRUPT_VECTOR_COUNT,
+    IVT_BASE, JOURNAL_LENGTH, LOOP_HISTORY_LENGTH, MAX_FRAME_DEPTH, MAX_INSTRUCTION_LENGTH,
+    MAX_OPERANDS, PAGE_COUNT, PAGE_LENGTH, PROTECTED_REGION_COUNT, REGISTER_COUNT,
+    RUN_PROGRAM_STEP_LIMIT, STACK_LOG_LENGTH, SYSCALL_COUNT,
 };
 
+/// Characters in a formatted register value: the `0x` prefix plus two hex digits per byte
+const REGISTER_VALUE_WIDTH: usize = 2 + 2 * mem::size_of::<VMSize>();
+
+/// The bytes of backing storage a machine with `mem` bytes of main memory holds. Every
+/// address is a single `u8` cell, so this is `mem` itself
+pub const fn backing_len(mem: usize) -> usize {
+    mem
+}
+
+/// A host function which instructions can call into with `Syscall`
+pub type SyscallHandler<const MEMORY: usize, const EXTRA_GPR: usize> =
+    fn(&mut Machine<MEMORY, EXTRA_GPR>) -> Result<(), MachineError>;
+
+/// A host function called with the error when a step faults, which can inspect and
+/// patch up the machine before choosing how to carry on
+pub type FaultHandler<const MEMORY: usize, const EXTRA_GPR: usize> =
+    fn(&mut Machine<MEMORY, EXTRA_GPR>, &MachineError) -> FaultAction;
+
 #[derive(Clone)]
-pub struct Machine<const MEMORY: usize>
-where
-    [(); MEMORY * mem::size_of::<u8>()]:,
-{
+pub struct Machine<const MEMORY: usize, const EXTRA_GPR: usize = 0> {
     pub registers: [VMSize; REGISTER_COUNT as usize],
-    pub stack_frame_size: VMSize,
-    pub memory: [u8; MEMORY * mem::size_of::<u8>()],
+    /// General purpose registers beyond R8 for the host to keep state in. They're saved
+    /// and restored with R1..=R8 by calls, interrupts and PushAll, but instructions can't
+    /// name them, so programs can't read or write them directly
+    pub extra_registers: [VMSize; EXTRA_GPR],
+    pub stack_frame_size: FrameSize,
+    pub memory: [u8; MEMORY],
+    pub halted: bool,
+    /// The low byte of ACC when the machine last halted, so programs can report
+    /// success or failure to the host
+    pub exit_code: Option<u8>,
+    pub flags: Flags,
+    /// The lowest address the stack may grow down into. A stack growing up may not
+    /// reach this address instead, unless it's left at zero
+    pub stack_limit: Ptr,
+    /// Rejects instruction writes to the reserved IP, SP and FP registers
+    pub strict_registers: bool,
+    /// Rejects writes to address zero, catching null pointer style bugs once a
+    /// program has been loaded
+    pub trap_null_writes: bool,
+    protected: Vec<Range<Ptr>, PROTECTED_REGION_COUNT>,
+    interrupts: Deque<u8, INTERRUPT_QUEUE_LENGTH>,
+    devices: Vec<MappedDevice, DEVICE_COUNT>,
+    syscalls: [Option<SyscallHandler<MEMORY, EXTRA_GPR>>; SYSCALL_COUNT as usize],
+    fault_handler: Option<FaultHandler<MEMORY, EXTRA_GPR>>,
+    /// Memory reads and writes per page, present while access stats are enabled
+    access_stats: Option<[PageStats; PAGE_COUNT]>,
+    /// A bit per address marking the opcodes executed, present while coverage is enabled
+    coverage: Option<[u8; COVERAGE_LENGTH]>,
+    histogram: [u64; 256],
+    /// Undo records for the most recent steps, present while journaling is enabled
+    journal: Option<Deque<UndoRecord<EXTRA_GPR>, JOURNAL_LENGTH>>,
+    /// The undo record of the step in progress, which memory writes are added to
+    pending: Option<UndoRecord<EXTRA_GPR>>,
+    /// The most recent word pushes and pops, present while stack logging is enabled
+    stack_log: Option<Deque<StackOp, STACK_LOG_LENGTH>>,
+    /// Events not yet drained, present while the event stream is enabled
+    events: Option<Deque<MachineEvent, EVENT_QUEUE_LENGTH>>,
+    /// Instruction bytes to fetch ahead of memory, so an instruction can run from
+    /// outside memory without being written into it
+    staged: Deque<u8, MAX_INSTRUCTION_LENGTH>,
+    stack_direction: StackDirection,
+    acc_saving: AccSaving,
+    /// The address the stack grows away from, where SP and FP start
+    stack_origin: Ptr,
 }
 
-impl<const MEMORY: usize> Machine<MEMORY>
-where
-    [(); MEMORY * mem::size_of::<u8>()]:,
-{
-    fn new() -> Self {
+impl<const MEMORY: usize, const EXTRA_GPR: usize> Machine<MEMORY, EXTRA_GPR> {
+    /// Creates a machine with `MEMORY` bytes of zeroed main memory
+    pub fn new() -> Self {
+        Self::new_filled(0)
+    }
+
+    /// Creates a machine with every byte of main memory set to `byte`, so a poison
+    /// pattern such as `0xCC` shows up wherever a program hasn't written
+    pub fn new_filled(byte: u8) -> Self {
         let mut machine = Machine {
             registers: [0; REGISTER_COUNT as usize],
-            stack_frame_size: 0,
-            memory: [0; MEMORY * mem::size_of::<u8>()],
+            extra_registers: [0; EXTRA_GPR],
+            stack_frame_size: FrameSize::default(),
+            memory: [byte; MEMORY],
+            halted: false,
+            exit_code: None,
+            flags: Flags::default(),
+            stack_limit: Ptr(0),
+            strict_registers: false,
+            trap_null_writes: false,
+            protected: Vec::new(),
+            interrupts: Deque::new(),
+            devices: Vec::new(),
+            syscalls: [None; SYSCALL_COUNT as usize],
+            fault_handler: None,
+            access_stats: None,
+            coverage: None,
+            histogram: [0; 256],
+            journal: None,
+            pending: None,
+            stack_log: None,
+            events: None,
+            staged: Deque::new(),
+            stack_direction: StackDirection::Down,
+            acc_saving: AccSaving::CallerSaved,
+            stack_origin: Self::stack_base(),
         };
         // Initialize the stack and frame pointers to the end of the main memory region for now
-        machine.registers[SP as usize] = (MEMORY - 1 - 1) as VMSize;
-        machine.registers[FP as usize] = (MEMORY - 1 - 1) as VMSize;
+        machine.registers[SP as usize] = Self::stack_base().0;
+        machine.registers[FP as usize] = Self::stack_base().0;
         machine
     }
 
+    /// The number of bytes of main memory
+    pub fn memory_len(&self) -> usize {
+        backing_len(MEMORY)
+    }
+
+    /// The highest address in main memory
+    pub const fn max_addr() -> Ptr {
+        Ptr((MEMORY - 1) as VMSize)
+    }
+
+    /// The last word aligned address in main memory, where the stack begins
+    pub const fn stack_base() -> Ptr {
+        Ptr(((MEMORY - 1 - 1) & !1) as VMSize)
+    }
+
+    /// Empties the stack and moves it to grow from `base` in the given direction.
+    /// New machines grow their stack down from `stack_base`
+    pub fn set_stack(&mut self, direction: StackDirection, base: Ptr) -> Result<(), MachineError> {
+        if base.0 & 1 != 0 {
+            return Err(MachineError::MisalignedStackPointer(base.0));
+        }
+        self.stack_direction = direction;
+        self.stack_origin = base;
+        self.registers[SP as usize] = base.0;
+        self.registers[FP as usize] = base.0;
+        self.stack_frame_size = FrameSize::default();
+        Ok(())
+    }
+
+    pub fn stack_direction(&self) -> StackDirection {
+        self.stack_direction
+    }
+
+    /// Chooses whether calls save and restore ACC along with the other registers. Frames
+    /// pushed under one convention can't be popped under the other, so only change it
+    /// while no calls are in progress
+    pub fn set_acc_saving(&mut self, saving: AccSaving) {
+        self.acc_saving = saving;
+    }
+
+    pub fn acc_saving(&self) -> AccSaving {
+        self.acc_saving
+    }
+
+    /// The number of bytes the stack can still grow by before reaching its limit, which
+    /// is how many bytes pushes may still write
+    pub fn stack_free(&self) -> u16 {
+        let sp = self.registers[SP as usize] as usize;
+        match self.stack_direction {
+            // The next push writes the word at SP, so the free bytes run from the limit
+            // up to the end of that word, as long as SP can move that far
+            StackDirection::Down => {
+                (sp + 2).saturating_sub(self.stack_limit.0 as usize).min(sp) as u16
+            }
+            StackDirection::Up => self.stack_end().saturating_sub(sp) as u16,
+        }
+    }
+
+    /// Reads the live stack contents as words, from the top of the stack down to its base
+    pub fn stack_words(&self) -> impl Iterator<Item = u16> + '_ {
+        let sp = self.registers[SP as usize];
+        let words = sp.abs_diff(self.stack_origin.0) / 2;
+        (1..=words).map(move |word| self.get16(Ptr(self.toward_base(sp, word * 2))))
+    }
+
+    /// The address `bytes` further from the top of the stack, towards its base
+    #[inline]
+    fn toward_base(&self, addr: VMSize, bytes: u16) -> VMSize {
+        match self.stack_direction {
+            StackDirection::Down => addr.wrapping_add(bytes),
+            StackDirection::Up => addr.wrapping_sub(bytes),
+        }
+    }
+
+    /// The address an upward growing stack may not reach
     #[inline]
-    pub fn fetch(&mut self) -> u8 {
+    fn stack_end(&self) -> usize {
+        match self.stack_limit.0 {
+            0 => MEMORY,
+            limit => (limit as usize).min(MEMORY),
+        }
+    }
+
+    /// Whether `bytes` more can be pushed without passing the stack limit, or moving
+    /// SP off the edge of memory. Agrees with `stack_free`
+    #[inline]
+    fn stack_has_room(&self, bytes: u16) -> bool {
+        let sp = self.registers[SP as usize];
+        match self.stack_direction {
+            // The lowest byte a push writes is the last byte of the word at SP less
+            // the bytes pushed, which may not fall below the limit
+            StackDirection::Down => {
+                sp as usize + 2 >= self.stack_limit.0 as usize + bytes as usize && sp >= bytes
+            }
+            StackDirection::Up => sp as usize + bytes as usize <= self.stack_end(),
+        }
+    }
+
+    /// Words only move on and off the stack at even addresses, so an odd SP means a byte
+    /// push is still unpaired or SP was overwritten
+    #[inline]
+    fn check_sp_aligned(&self) -> Result<(), MachineError> {
+        let sp = self.registers[SP as usize];
+        if sp & 1 != 0 {
+            return Err(MachineError::MisalignedStackPointer(sp));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn grow_sp(&mut self, bytes: u16) -> Result<(), MachineError> {
+        self.stack_frame_size.grow(bytes)?;
+        let sp = self.registers[SP as usize];
+        self.registers[SP as usize] = match self.stack_direction {
+            StackDirection::Down => sp - bytes,
+            StackDirection::Up => sp + bytes,
+        };
+        Ok(())
+    }
+
+    /// Fails without moving SP when the current frame holds fewer than `bytes` bytes
+    #[inline]
+    fn shrink_sp(&mut self, bytes: u16) -> Result<(), MachineError> {
+        self.stack_frame_size.shrink(bytes)?;
+        self.registers[SP as usize] = self.toward_base(self.registers[SP as usize], bytes);
+        Ok(())
+    }
+
+    /// Reads the next instruction byte and moves the IP past it. Bytes staged by
+    /// `execute_bytes` or `execute_decoded` are read before memory
+    #[inline]
+    pub fn fetch(&mut self) -> Result<u8, MachineError> {
         let instruction_address = self.registers[IP as usize];
-        let instruction = self.memory[instruction_address as usize];
-        self.registers[IP as usize] += 1;
-        instruction
+        let instruction = match self.staged.pop_front() {
+            Some(byte) => byte,
+            None => {
+                self.check_bounds(Ptr(instruction_address), 1)?;
+                self.get(Ptr(instruction_address))
+            }
+        };
+        self.registers[IP as usize] = instruction_address.wrapping_add(1);
+        Ok(instruction)
+    }
+
+    /// Reads the opcode at the IP without advancing past it
+    pub fn peek_opcode(&self) -> Result<u8, MachineError> {
+        let addr = Ptr(self.registers[IP as usize]);
+        self.check_bounds(addr, 1)?;
+        Ok(self.get(addr))
+    }
+
+    /// The number of bytes the instruction at `addr` occupies, its opcode plus operands,
+    /// found without executing it
+    pub fn instruction_len_at(&self, addr: Ptr) -> Result<usize, MachineError> {
+        self.check_bounds(addr, 1)?;
+        let opcode = self.get(addr);
+        let instruction = Instructions::try_from(opcode)
+            .map_err(|_| MachineError::InvalidInstruction { opcode, at: addr })?;
+        Ok(instruction.encoded_len())
+    }
+
+    /// Decodes the instruction at `addr` along with its operands, without executing it
+    pub fn decode_at(
+        &self,
+        addr: Ptr,
+    ) -> Result<(Instructions, Vec<Operand, MAX_OPERANDS>), MachineError> {
+        let len = self.instruction_len_at(addr)?;
+        self.check_bounds(addr, len)?;
+        let instruction = Instructions::try_from(self.get(addr))
+            .expect("instruction_len_at should have checked the opcode");
+        let start = addr.0 as usize + 1;
+        let operands = instruction.decode_operands(&self.memory[start..start + len - 1])?;
+        Ok((instruction, operands))
     }
 
     #[inline]
-    pub fn fetch16(&mut self) -> u16 {
-        let instruction_address = Ptr(self.registers[IP as usize]);
-        let result = self.get16(instruction_address);
-        self.registers[IP as usize] += 2;
-        result
+    pub fn fetch16(&mut self) -> Result<u16, MachineError> {
+        let high = self.fetch()?;
+        let low = self.fetch()?;
+        Ok((high as u16) << 8 | low as u16)
     }
 
+    /// Reads a byte straight out of memory for the host, panicking past the end of memory.
+    /// Instructions read through bounds checked paths instead
     #[inline]
     pub fn get(&self, addr: Ptr) -> u8 {
         self.memory[addr.0 as usize]
     }
 
+    /// Reads a word straight out of memory for the host, panicking past the end of memory
     #[inline]
     pub fn get16(&self, addr: Ptr) -> u16 {
         let high = self.get(addr);
@@ -61,174 +320,1366 @@ where
         (high as u16) << 8 | low as u16
     }
 
+    /// Marks a region of memory as read only so any writes into it are rejected
+    pub fn protect(&mut self, range: Range<Ptr>) -> Result<(), MachineError> {
+        self.protected
+            .push(range)
+            .map_err(|_| MachineError::TooManyProtectedRegions)
+    }
+
+    /// Removes a region previously marked read only by `protect`
+    pub fn unprotect(&mut self, range: Range<Ptr>) {
+        self.protected.retain(|protected| *protected != range);
+    }
+
+    #[inline]
+    fn check_writable(&self, addr: Ptr) -> Result<(), MachineError> {
+        if self.trap_null_writes && addr.0 == 0 {
+            return Err(MachineError::NullWrite);
+        }
+        if self.protected.iter().any(|range| range.contains(&addr)) {
+            return Err(MachineError::WriteProtected(addr));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn set8(&mut self, addr: Ptr, data: u8) -> Result<(), MachineError> {
+        self.check_bounds(addr, 1)?;
+        self.check_writable(addr)?;
+        self.count_access(addr, true);
+        self.store8(addr, data);
+        Ok(())
+    }
+
+    /// Writes a byte without checking or counting it, noting the old value for `step_back`
     #[inline]
-    pub fn set8(&mut self, addr: Ptr, data: u8) {
+    fn store8(&mut self, addr: Ptr, data: u8) {
+        let change = MemoryChange {
+            addr,
+            old: self.memory[addr.0 as usize],
+            new: data,
+        };
+        if let Some(record) = &mut self.pending {
+            record.overflowed |= record.memory.push(change).is_err();
+        }
+        self.emit(MachineEvent::MemoryWritten(change));
         self.memory[addr.0 as usize] = data;
     }
 
     #[inline]
-    pub fn set16(&mut self, addr: Ptr, data: u16) {
-        self.set8(addr, (data >> 8) as u8);
-        self.set8(addr + 1, data as u8);
+    pub fn set16(&mut self, addr: Ptr, data: u16) -> Result<(), MachineError> {
+        // Check both bytes up front so a rejected write leaves memory untouched
+        self.check_bounds(addr, 2)?;
+        self.check_writable(addr)?;
+        self.check_writable(addr + 1)?;
+        self.count_access(addr, true);
+        self.store8(addr, (data >> 8) as u8);
+        self.store8(addr + 1, data as u8);
+        Ok(())
+    }
+
+    /// Reads a byte on behalf of an instruction, counting it in the access stats
+    #[inline]
+    fn load8(&mut self, addr: Ptr) -> Result<u8, MachineError> {
+        self.check_bounds(addr, 1)?;
+        self.count_access(addr, false);
+        Ok(self.get(addr))
+    }
+
+    /// Reads a word on behalf of an instruction, counting it in the access stats
+    #[inline]
+    fn load16(&mut self, addr: Ptr) -> Result<u16, MachineError> {
+        self.check_bounds(addr, 2)?;
+        self.count_access(addr, false);
+        Ok(self.get16(addr))
+    }
+
+    /// Counts an access in the access stats. Only called once the access has passed
+    /// `check_bounds`
+    #[inline]
+    fn count_access(&mut self, addr: Ptr, write: bool) {
+        if let Some(stats) = &mut self.access_stats {
+            let page = &mut stats[addr.0 as usize / PAGE_LENGTH];
+            if write {
+                page.writes += 1;
+            } else {
+                page.reads += 1;
+            }
+        }
+    }
+
+    /// Starts or stops counting the memory reads and writes instructions make in each
+    /// page. Stopping discards the counts so far
+    pub fn set_access_stats(&mut self, enabled: bool) {
+        if !enabled {
+            self.access_stats = None;
+        } else if self.access_stats.is_none() {
+            self.access_stats = Some([PageStats::default(); PAGE_COUNT]);
+        }
+    }
+
+    /// The reads and writes counted in each `PAGE_LENGTH` byte page of main memory, indexed
+    /// by page, or nothing unless access stats are enabled. Word accesses count once, and
+    /// instruction fetches aren't counted at all
+    pub fn access_stats(&self) -> &[PageStats] {
+        match &self.access_stats {
+            Some(stats) => &stats[..MEMORY.div_ceil(PAGE_LENGTH)],
+            None => &[],
+        }
+    }
+
+    /// Starts or stops marking the address of every opcode executed, for tools such as
+    /// coverage guided fuzzers. Stopping discards the coverage so far
+    pub fn set_coverage(&mut self, enabled: bool) {
+        if !enabled {
+            self.coverage = None;
+        } else if self.coverage.is_none() {
+            self.coverage = Some([0; COVERAGE_LENGTH]);
+        }
+    }
+
+    /// The addresses opcodes were executed from as a bitset, where bit `addr % 8` of byte
+    /// `addr / 8` marks `addr`, or nothing unless coverage is enabled. Operand bytes and
+    /// serviced interrupts aren't marked
+    pub fn coverage(&self) -> &[u8] {
+        match &self.coverage {
+            Some(coverage) => &coverage[..MEMORY.div_ceil(8)],
+            None => &[],
+        }
+    }
+
+    /// Writes a value into a register, rejecting values which would leave SP or FP
+    /// misaligned. Writing to IP is allowed and acts as an unconditional jump, unless
+    /// the machine is strict about writes to reserved registers
+    #[inline]
+    pub fn set_reg(&mut self, reg: Registers, value: VMSize) -> Result<(), MachineError> {
+        if self.strict_registers && reg.is_reserved() {
+            return Err(MachineError::ReservedRegisterWrite(reg));
+        }
+        if matches!(reg, SP | FP) && value & 1 != 0 {
+            return Err(MachineError::MisalignedStackPointer(value));
+        }
+        self.registers[reg as usize] = value;
+        Ok(())
+    }
+
+    /// Writes several registers in order with `set_reg`, stopping at the first rejected write
+    pub fn set_registers(&mut self, values: &[(Registers, VMSize)]) -> Result<(), MachineError> {
+        for &(reg, value) in values {
+            self.set_reg(reg, value)?;
+        }
+        Ok(())
+    }
+
+    /// Maps a device onto the word at the provided address, so instructions reading or
+    /// writing that word talk to the device instead of main memory
+    pub fn map_device(&mut self, addr: Ptr, device: Device) -> Result<(), MachineError> {
+        self.devices
+            .push(MappedDevice { addr, device })
+            .map_err(|_| MachineError::TooManyDevices)
+    }
+
+    /// Registers the host function called when instructions make the numbered syscall
+    pub fn register_syscall(
+        &mut self,
+        number: u8,
+        handler: SyscallHandler<MEMORY, EXTRA_GPR>,
+    ) -> Result<(), MachineError> {
+        let slot = self
+            .syscalls
+            .get_mut(number as usize)
+            .ok_or(MachineError::InvalidSyscall(number))?;
+        *slot = Some(handler);
+        Ok(())
+    }
+
+    /// Reads a word as instructions see it, through any device mapped at the address
+    #[inline]
+    pub fn read16(&mut self, addr: Ptr) -> Result<u16, MachineError> {
+        match self.devices.iter_mut().find(|mapped| mapped.addr == addr) {
+            Some(mapped) => Ok(mapped.device.read()),
+            None => self.load16(addr),
+        }
+    }
+
+    /// Writes a word as instructions see it, through any device mapped at the address
+    #[inline]
+    pub fn write16(&mut self, addr: Ptr, data: u16) -> Result<(), MachineError> {
+        match self.devices.iter_mut().find(|mapped| mapped.addr == addr) {
+            Some(mapped) => {
+                mapped.device.write(data);
+                Ok(())
+            }
+            None => self.set16(addr, data),
+        }
     }
 
     #[inline]
     pub fn fetch_register_id(&mut self) -> Result<Registers, MachineError> {
-        let reg = self.fetch().try_into()?;
+        let reg = self.fetch()?.try_into()?;
         Ok(reg)
     }
 
+    /// Reads the register named by an id from an untrusted source, such as instruction
+    /// bytes, failing with `InvalidRegister` rather than indexing past the registers
+    pub fn reg_checked(&self, id: u8) -> Result<VMSize, MachineError> {
+        let reg = Registers::try_from(id)?;
+        Ok(self.registers[reg as usize])
+    }
+
+    /// Fetches a register id and reads the register it names with `reg_checked`
+    #[inline]
+    fn fetch_register_value(&mut self) -> Result<VMSize, MachineError> {
+        let id = self.fetch()?;
+        self.reg_checked(id)
+    }
+
     #[inline]
-    pub fn push(&mut self, value: u16) {
+    pub fn push(&mut self, value: u16) -> Result<(), MachineError> {
         let sp_addr = Ptr(self.registers[SP as usize]);
-        self.set16(sp_addr, value);
-        self.registers[SP  as usize] -= 2;
-        self.stack_frame_size += 2;
+        self.check_sp_aligned()?;
+        // Refuse to write past the limit or move SP off the edge of memory
+        if !self.stack_has_room(2) {
+            return Err(MachineError::StackOverflow);
+        }
+        // Stack words go through set16 here and get16 in pop, so they share a byte order
+        self.set16(sp_addr, value)?;
+        self.grow_sp(2)?;
+        self.log_stack_op(StackOp::Push {
+            value,
+            sp: self.registers[SP as usize],
+        });
+        Ok(())
     }
 
     #[inline]
-    pub fn pop(&mut self) -> u16 {
-        self.registers[SP as usize] += 2;
+    pub fn pop(&mut self) -> Result<u16, MachineError> {
+        self.check_sp_aligned()?;
+        self.shrink_sp(2)?;
         let stack_addr = Ptr(self.registers[SP as usize]);
-        self.stack_frame_size -= 2;
-        self.get16(stack_addr)
+        let value = self.load16(stack_addr)?;
+        self.log_stack_op(StackOp::Pop {
+            value,
+            sp: stack_addr.0,
+        });
+        Ok(value)
+    }
+
+    /// Starts or stops recording word pushes and pops for `stack_log`. Stopping discards
+    /// everything recorded so far
+    pub fn set_stack_logging(&mut self, enabled: bool) {
+        if !enabled {
+            self.stack_log = None;
+        } else if self.stack_log.is_none() {
+            self.stack_log = Some(Deque::new());
+        }
+    }
+
+    /// The most recent word pushes and pops, oldest first, empty unless stack logging is
+    /// enabled. Byte pushes and pops, and SP moved directly when a frame is discarded,
+    /// aren't logged
+    pub fn stack_log(&self) -> Vec<StackOp, STACK_LOG_LENGTH> {
+        self.stack_log.iter().flatten().copied().collect()
+    }
+
+    #[inline]
+    fn log_stack_op(&mut self, op: StackOp) {
+        if let Some(log) = &mut self.stack_log {
+            if log.is_full() {
+                log.pop_front();
+            }
+            let _ = log.push_back(op);
+        }
+        self.emit(MachineEvent::Stack(op));
+    }
+
+    /// Starts or stops streaming events from each step for `drain_events`. Stopping
+    /// discards any events not yet drained
+    pub fn set_events(&mut self, enabled: bool) {
+        if !enabled {
+            self.events = None;
+        } else if self.events.is_none() {
+            self.events = Some(Deque::new());
+        }
+    }
+
+    /// Takes the events streamed since the last drain, oldest first, dropping each as
+    /// it's yielded. Once `EVENT_QUEUE_LENGTH` events are waiting the oldest are lost
+    pub fn drain_events(&mut self) -> impl Iterator<Item = MachineEvent> + '_ {
+        core::iter::from_fn(move || self.events.as_mut()?.pop_front())
     }
 
     #[inline]
-    pub fn push_state(&mut self) {
-        // Capture the current register state on the stack
+    fn emit(&mut self, event: MachineEvent) {
+        if let Some(events) = &mut self.events {
+            if events.is_full() {
+                events.pop_front();
+            }
+            let _ = events.push_back(event);
+        }
+    }
+
+    /// Pushes a single byte, leaving SP odd until another byte is pushed or popped
+    #[inline]
+    pub fn push8(&mut self, value: u8) -> Result<(), MachineError> {
+        let sp_addr = Ptr(self.registers[SP as usize]);
+        if !self.stack_has_room(1) {
+            return Err(MachineError::StackOverflow);
+        }
+        // The byte takes the half of the free word slot at SP nearest the stack base
+        match self.stack_direction {
+            StackDirection::Down => self.set8(sp_addr + 1, value)?,
+            StackDirection::Up => self.set8(sp_addr, value)?,
+        }
+        self.grow_sp(1)
+    }
+
+    #[inline]
+    pub fn pop8(&mut self) -> Result<u8, MachineError> {
+        self.shrink_sp(1)?;
+        let sp_addr = Ptr(self.registers[SP as usize]);
+        Ok(match self.stack_direction {
+            StackDirection::Down => self.load8(sp_addr + 1)?,
+            StackDirection::Up => self.load8(sp_addr)?,
+        })
+    }
+
+    #[inline]
+    pub fn push_registers(&mut self) -> Result<(), MachineError> {
         for reg in R1 as usize..=R8 as usize {
-            self.push(self.registers[reg]);
+            self.push(self.registers[reg])?;
+        }
+        for reg in 0..EXTRA_GPR {
+            self.push(self.extra_registers[reg])?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn pop_registers(&mut self) -> Result<(), MachineError> {
+        for reg in (0..EXTRA_GPR).rev() {
+            self.extra_registers[reg] = self.pop()?;
+        }
+        for reg in (R1 as usize..=R8 as usize).rev() {
+            self.registers[reg] = self.pop()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn push_state(&mut self) -> Result<(), MachineError> {
+        // Frames stay word aligned, so bytes pushed with push8 must be paired up first
+        let sp = self.registers[SP as usize];
+        if sp & 1 != 0 {
+            return Err(MachineError::MisalignedStackPointer(sp));
         }
+        // Capture the current register state on the stack, leaving ACC free to carry
+        // a return value back to the caller unless the callee is to preserve it
+        if self.acc_saving == AccSaving::CalleeSaved {
+            self.push(self.registers[ACC as usize])?;
+        }
+        self.push_registers()?;
         // Capture the current instruction pointer on the stack
-        self.push(self.registers[IP as usize]);
+        self.push(self.registers[IP as usize])?;
         // Prepare and reset the stack frame values
-        self.push(self.stack_frame_size + 2);
+        let saved_size = self
+            .stack_frame_size
+            .get()
+            .checked_add(2)
+            .ok_or(MachineError::FrameCorruption)?;
+        self.push(saved_size)?;
         self.registers[FP as usize] = self.registers[SP as usize];
-        self.stack_frame_size = 0;
+        self.stack_frame_size = FrameSize::default();
+        Ok(())
     }
 
+    /// Returns from the current frame, failing with `FrameCorruption` when the saved frame
+    /// size or arg count would tear down more of the caller's frame than it holds
     #[inline]
-    pub fn pop_state(&mut self) {
+    pub fn pop_state(&mut self) -> Result<(), MachineError> {
         let frame_pointer_addr = self.registers[FP as usize];
-        self.registers[SP as usize] = frame_pointer_addr;
-        self.stack_frame_size = self.pop();
+        // Restore the prior frame size directly since the current frame is being
+        // discarded, popping it would underflow when the frame holds no values. The
+        // saved size counts its own word, which is gone once SP moves past it
+        self.registers[SP as usize] = self.toward_base(frame_pointer_addr, 2);
+        let saved_size = self.load16(Ptr(self.registers[SP as usize]))?;
+        self.stack_frame_size = FrameSize::new(
+            saved_size
+                .checked_sub(2)
+                .ok_or(MachineError::FrameCorruption)?,
+        );
         // Restore the prior instruction pointer from the stack
-        self.registers[IP as usize] = self.pop();
+        self.registers[IP as usize] = self.pop()?;
         // Restore the prior register state from the stack
-        for reg in (R1 as usize..=R8 as usize).rev() {
-            self.registers[reg] = self.pop();
+        self.pop_registers()?;
+        if self.acc_saving == AccSaving::CalleeSaved {
+            self.registers[ACC as usize] = self.pop()?;
         }
         // Account for args from the prior function call
-        let n_args = self.pop();
+        let n_args = self.pop()?;
         for _arg in 0..n_args {
-            self.pop();
+            self.pop()?;
+        }
+        // The caller's frame ends where its values started being pushed
+        self.registers[FP as usize] =
+            self.toward_base(self.registers[SP as usize], self.stack_frame_size.get());
+        debug_assert!(
+            self.frame_consistent(),
+            "pop_state left an inconsistent frame"
+        );
+        Ok(())
+    }
+
+    /// Walks the chain of call frames from the current one outwards. Each frame's saved
+    /// size covers everything from its own FP to the caller's, so moving one FP that far
+    /// towards the stack base gives the next, until the chain reaches the base
+    pub fn frames(&self) -> Vec<FrameInfo, MAX_FRAME_DEPTH> {
+        let mut frames = Vec::new();
+        let mut fp = self.registers[FP as usize];
+        loop {
+            let in_bounds = match self.stack_direction {
+                StackDirection::Down => fp < self.stack_origin.0 && fp as usize + 5 < MEMORY,
+                StackDirection::Up => fp > self.stack_origin.0 && fp >= 4,
+            };
+            if !in_bounds {
+                break;
+            }
+            let saved_size = self.get16(Ptr(self.toward_base(fp, 2)));
+            let return_addr = Ptr(self.get16(Ptr(self.toward_base(fp, 4))));
+            let frame = FrameInfo {
+                fp: Ptr(fp),
+                return_addr,
+            };
+            if saved_size == 0 || frames.push(frame).is_err() {
+                break;
+            }
+            fp = self.toward_base(fp, saved_size);
+        }
+        frames
+    }
+
+    /// Whether FP sits exactly `stack_frame_size` bytes from SP towards the stack base,
+    /// as it does while values are only moved on and off the stack by pushes, pops,
+    /// calls and returns
+    pub fn frame_consistent(&self) -> bool {
+        let sp = self.registers[SP as usize];
+        let fp = self.registers[FP as usize];
+        let distance = match self.stack_direction {
+            StackDirection::Down => fp.checked_sub(sp),
+            StackDirection::Up => sp.checked_sub(fp),
+        };
+        distance == Some(self.stack_frame_size.get())
+    }
+
+    /// Moves the IP by a signed offset, wrapping around the address space
+    #[inline]
+    pub fn jump_relative(&mut self, offset: i16) {
+        let ip = self.registers[IP as usize];
+        self.registers[IP as usize] = ip.wrapping_add_signed(offset);
+    }
+
+    /// Queues an interrupt to be serviced ahead of the next instruction fetch
+    pub fn raise_interrupt(&mut self, int_no: u8) -> Result<(), MachineError> {
+        if int_no >= INTERRUPT_VECTOR_COUNT {
+            return Err(MachineError::InvalidInterrupt(int_no));
+        }
+        self.interrupts
+            .push_back(int_no)
+            .map_err(|_| MachineError::InterruptQueueFull)
+    }
+
+    /// Writes the handler address for an interrupt into the interrupt vector table
+    pub fn set_handler(&mut self, int_no: u8, addr: Ptr) -> Result<(), MachineError> {
+        if int_no >= INTERRUPT_VECTOR_COUNT {
+            return Err(MachineError::InvalidInterrupt(int_no));
+        }
+        self.set16(IVT_BASE + int_no as u16 * 2, addr.0)
+    }
+
+    /// Reads the handler address for an interrupt from the interrupt vector table
+    pub fn get_handler(&self, int_no: u8) -> Result<Ptr, MachineError> {
+        if int_no >= INTERRUPT_VECTOR_COUNT {
+            return Err(MachineError::InvalidInterrupt(int_no));
         }
-        self.registers[FP as usize] = frame_pointer_addr + self.stack_frame_size;
+        let entry = IVT_BASE + int_no as u16 * 2;
+        self.check_bounds(entry, 2)?;
+        Ok(Ptr(self.get16(entry)))
+    }
+
+    /// Stashes the current machine state and moves the IP to the handler
+    /// registered in the interrupt vector table. The flags and ACC are saved ahead
+    /// of the frame whatever the `AccSaving` mode, so an interrupt landing between a
+    /// compare and its jump, or before ACC is used, goes unnoticed
+    pub fn interrupt(&mut self, int_no: u8) -> Result<(), MachineError> {
+        let handler_addr = self.get_handler(int_no)?.0;
+        self.push(self.flags.bits())?;
+        self.push(self.registers[ACC as usize])?;
+        // Handlers take no args, but the frame still records the arg count
+        self.call(handler_addr, 0)
+    }
+
+    /// Pushes the arg count for the args already on the stack, stashes the machine
+    /// state and moves the IP to the subroutine
+    fn call(&mut self, subroutine_addr: u16, n_args: u16) -> Result<(), MachineError> {
+        self.push(n_args)?;
+        self.push_state()?;
+        self.registers[IP as usize] = subroutine_addr;
+        Ok(())
     }
 
     pub fn get_window(&self, addr: Ptr, len: VMSize) -> MemoryWindow {
         let data = &self.memory[addr.0 as usize..addr.0 as usize + len as usize];
-        MemoryWindow { addr, data }
+        MemoryWindow::new(addr, data)
+    }
+
+    /// Compares a region of memory against the expected bytes, returning the first
+    /// mismatch as `(addr, expected, actual)`. Panics if the region runs past the end
+    /// of memory, as `get_window` does
+    pub fn assert_region(&self, addr: Ptr, expected: &[u8]) -> Result<(), (Ptr, u8, u8)> {
+        let window = self.get_window(addr, expected.len() as VMSize);
+        match window
+            .data()
+            .iter()
+            .zip(expected)
+            .position(|(actual, expected)| actual != expected)
+        {
+            Some(offset) => Err((addr + offset, expected[offset], window.data()[offset])),
+            None => Ok(()),
+        }
+    }
+
+    /// Variant of `get_window` which marks where the IP, SP and FP point when formatted
+    pub fn get_window_annotated(&self, addr: Ptr, len: VMSize) -> AnnotatedWindow<'_> {
+        AnnotatedWindow::new(
+            self.get_window(addr, len),
+            Ptr(self.registers[IP as usize]),
+            Ptr(self.registers[SP as usize]),
+            Ptr(self.registers[FP as usize]),
+        )
+    }
+
+    /// Bounds checked variant of `get_window` which fails rather than panics when
+    /// the window would run past the end of memory
+    pub fn try_get_window(&self, addr: Ptr, len: VMSize) -> Result<MemoryWindow<'_>, MachineError> {
+        self.check_bounds(addr, len as usize)?;
+        let data = &self.memory[addr.0 as usize..addr.0 as usize + len as usize];
+        Ok(MemoryWindow::new(addr, data))
+    }
+
+    /// Best effort variant of `get_window` which shortens the window to end at the end of
+    /// memory, and starts it there when `addr` is already past the end
+    pub fn get_window_clamped(&self, addr: Ptr, len: VMSize) -> MemoryWindow<'_> {
+        let start = (addr.0 as usize).min(MEMORY);
+        let end = (start + len as usize).min(MEMORY);
+        MemoryWindow::new(Ptr(start as VMSize), &self.memory[start..end])
+    }
+
+    /// Streams the same hex dump `MemoryWindow`'s Debug gives into `out`, one value at a
+    /// time, so large regions can be dumped without building the whole text first. Like
+    /// `get_window_clamped`, the region is cut short at the end of memory
+    pub fn dump<W: Write>(&self, out: &mut W, addr: Ptr, len: VMSize) -> fmt::Result {
+        self.get_window_clamped(addr, len)
+            .fmt_annotated(out, |_, _| Ok(()))
+    }
+
+    /// Writes a program into memory starting at the provided address
+    pub fn load_program(&mut self, at: Ptr, program: &[u8]) -> Result<(), MachineError> {
+        self.check_bounds(at, program.len())?;
+        for (offset, &byte) in program.iter().enumerate() {
+            self.set8(at + offset, byte)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes a single instruction and writes it over the bytes at `at`, for debuggers
+    /// and self modifying programs. Every byte is checked against the end of memory and
+    /// the protected regions before any is written, so a rejected patch changes nothing.
+    /// Operands which don't match the instruction's layout fail with `InvalidOperands`
+    pub fn patch(
+        &mut self,
+        at: Ptr,
+        instruction: Instructions,
+        operands: &[Operand],
+    ) -> Result<(), MachineError> {
+        let bytes = encode(instruction, operands).map_err(|_| MachineError::InvalidOperands {
+            opcode: instruction.into(),
+            at,
+        })?;
+        self.check_bounds(at, bytes.len())?;
+        for offset in 0..bytes.len() {
+            self.check_writable(at + offset)?;
+        }
+        self.load_program(at, &bytes)
+    }
+
+    /// Writes a flat binary image into memory and points the IP at its entry point
+    pub fn load_image(&mut self, at: Ptr, bytes: &[u8], entry: Ptr) -> Result<(), MachineError> {
+        self.load_program(at, bytes)?;
+        self.registers[IP as usize] = entry.0;
+        Ok(())
+    }
+
+    /// Copies a region of memory out into an owned buffer
+    pub fn read_bytes<const N: usize>(
+        &self,
+        addr: Ptr,
+        len: VMSize,
+    ) -> Result<Vec<u8, N>, MachineError> {
+        let window = self.try_get_window(addr, len)?;
+        Vec::from_slice(window.data()).map_err(|_| MachineError::BufferTooSmall)
+    }
+
+    /// Copies out the null terminated string at `addr`, without its terminator, stopping
+    /// early after `max` bytes. Fails if memory ends before either, or the string doesn't
+    /// fit in `N` bytes
+    pub fn read_cstr<const N: usize>(
+        &self,
+        addr: Ptr,
+        max: usize,
+    ) -> Result<Vec<u8, N>, MachineError> {
+        let mut text = Vec::new();
+        for offset in 0..max {
+            let at = addr + offset;
+            self.check_bounds(at, 1)?;
+            match self.get(at) {
+                0 => break,
+                byte => text.push(byte).map_err(|_| MachineError::BufferTooSmall)?,
+            }
+        }
+        Ok(text)
+    }
+
+    #[inline]
+    fn check_bounds(&self, addr: Ptr, len: usize) -> Result<(), MachineError> {
+        if addr.0 as usize + len > self.memory.len() {
+            return Err(MachineError::OutOfBounds(addr));
+        }
+        Ok(())
+    }
+
+    /// Searches memory from `from` onward for a byte sequence, returning where it starts
+    pub fn find(&self, needle: &[u8], from: Ptr) -> Option<Ptr> {
+        if needle.is_empty() {
+            return None;
+        }
+        self.memory
+            .get(from.0 as usize..)?
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map(|offset| from + offset)
+    }
+
+    /// Fills a number of words in a region of memory with the same value
+    pub fn fill_words(&mut self, dest: Ptr, value: u16, words: u16) -> Result<(), MachineError> {
+        self.check_bounds(dest, words as usize * 2)?;
+        for word in 0..words {
+            self.set16(dest + word * 2, value)?;
+        }
+        Ok(())
+    }
+
+    /// Copies a number of words between two regions of memory, which may overlap
+    pub fn copy_words(&mut self, src: Ptr, dest: Ptr, words: u16) -> Result<(), MachineError> {
+        self.check_bounds(src, words as usize * 2)?;
+        self.check_bounds(dest, words as usize * 2)?;
+        if dest > src {
+            // Copy back to front so an overlapping source isn't overwritten before it's read
+            for word in (0..words).rev() {
+                let value = self.load16(src + word * 2)?;
+                self.set16(dest + word * 2, value)?;
+            }
+        } else {
+            for word in 0..words {
+                let value = self.load16(src + word * 2)?;
+                self.set16(dest + word * 2, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes an Adler-32 checksum over a region of memory
+    pub fn checksum(&self, addr: Ptr, len: VMSize) -> Result<u32, MachineError> {
+        const MOD_ADLER: u32 = 65521;
+        let window = self.try_get_window(addr, len)?;
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in window.data() {
+            a = (a + byte as u32) % MOD_ADLER;
+            b = (b + a) % MOD_ADLER;
+        }
+        Ok(b << 16 | a)
     }
 
     pub fn execute(&mut self, instruction: Instructions) -> Result<(), MachineError> {
         match instruction {
+            Nop => {}
             MoveLitToReg => {
-                let lit_value = self.fetch16();
+                let lit_value = self.fetch16()?;
                 let reg_dest = self.fetch_register_id()?;
-                self.registers[reg_dest as usize] = lit_value;
+                self.set_reg(reg_dest, lit_value)?;
             }
             MoveRegToReg => {
-                let reg_src = self.fetch_register_id()?;
+                let value = self.fetch_register_value()?;
                 let reg_dest = self.fetch_register_id()?;
-                let value: VMSize = self.registers[reg_src as usize];
-                self.registers[reg_dest as usize] = value;
+                self.set_reg(reg_dest, value)?;
+            }
+            MoveAccToReg => {
+                let reg_dest = self.fetch_register_id()?;
+                self.set_reg(reg_dest, self.registers[ACC as usize])?;
             }
             MoveRegToMem => {
-                let reg_src = self.fetch_register_id()?;
-                let addr_dest = Ptr(self.fetch16());
-                let value = self.registers[reg_src as usize];
-                self.set16(addr_dest, value);
+                let value = self.fetch_register_value()?;
+                let addr_dest = Ptr(self.fetch16()?);
+                self.write16(addr_dest, value)?;
             }
             MoveMemToReg => {
-                let addr_src = Ptr(self.fetch16());
+                let addr_src = Ptr(self.fetch16()?);
                 let reg_dest = self.fetch_register_id()?;
-                let value = self.get16(addr_src);
-                self.registers[reg_dest as usize] = value;
+                let value = self.read16(addr_src)?;
+                self.set_reg(reg_dest, value)?;
             }
-            AddRegReg => {
+            MoveMemToRegIndexed => {
+                let base = self.fetch_register_value()?;
+                let index = self.fetch_register_value()?;
+                let displacement = self.fetch16()? as i16;
+                let reg_dest = self.fetch_register_id()?;
+                let addr = base
+                    .wrapping_add(index.wrapping_mul(2))
+                    .wrapping_add_signed(displacement);
+                let value = self.read16(Ptr(addr))?;
+                self.set_reg(reg_dest, value)?;
+            }
+            MoveLitToRegByte => {
+                let lit_value = self.fetch()? as VMSize;
+                let reg_dest = self.fetch_register_id()?;
+                self.set_reg(reg_dest, lit_value)?;
+            }
+            MoveMemToRegByte => {
+                let addr_src = Ptr(self.fetch16()?);
+                let reg_dest = self.fetch_register_id()?;
+                let value = self.load8(addr_src)? as VMSize;
+                self.set_reg(reg_dest, value)?;
+            }
+            MoveMemToRegByteSigned => {
+                let addr_src = Ptr(self.fetch16()?);
+                let reg_dest = self.fetch_register_id()?;
+                let value = self.load8(addr_src)? as i8 as i16 as VMSize;
+                self.set_reg(reg_dest, value)?;
+            }
+            Lea => {
+                let addr = self.fetch16()?;
+                let reg_dest = self.fetch_register_id()?;
+                self.set_reg(reg_dest, addr)?;
+            }
+            SwapReg => {
                 let reg_1 = self.fetch_register_id()?;
                 let reg_2 = self.fetch_register_id()?;
-                let val_1: VMSize = self.registers[reg_1 as usize];
-                let val_2: VMSize = self.registers[reg_2 as usize];
-                self.registers[ACC as usize] = val_1 + val_2;
+                let val_1 = self.registers[reg_1 as usize];
+                let val_2 = self.registers[reg_2 as usize];
+                self.set_reg(reg_1, val_2)?;
+                if let Err(error) = self.set_reg(reg_2, val_1) {
+                    // Put the first write back so the swap happens whole or not at all
+                    self.registers[reg_1 as usize] = val_1;
+                    return Err(error);
+                }
+            }
+            MoveRegToMemByte => {
+                let value = self.fetch_register_value()? as u8;
+                let addr_dest = Ptr(self.fetch16()?);
+                self.set8(addr_dest, value)?;
+            }
+            AddRegReg => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                let (sum, flags) = Flags::add(val_1, val_2, false);
+                self.registers[ACC as usize] = sum;
+                self.flags = flags;
+            }
+            Adc => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                let (sum, flags) = Flags::add(val_1, val_2, self.flags.carry);
+                self.registers[ACC as usize] = sum;
+                self.flags = flags;
+            }
+            AddRegRegDest => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                let reg_dest = self.fetch_register_id()?;
+                let (sum, flags) = Flags::add(val_1, val_2, false);
+                self.set_reg(reg_dest, sum)?;
+                self.flags = flags;
+            }
+            MulRegReg => {
+                let val_1 = self.fetch_register_value()? as u32;
+                let val_2 = self.fetch_register_value()? as u32;
+                let product = val_1 * val_2;
+                self.registers[ACC as usize] = product as VMSize;
+                self.registers[ACC2 as usize] = (product >> 16) as VMSize;
+            }
+            DivRegReg => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                if val_2 == 0 {
+                    return Err(MachineError::DivideByZero);
+                }
+                self.registers[ACC as usize] = val_1 / val_2;
+                self.registers[ACC2 as usize] = val_1 % val_2;
+            }
+            NegReg => {
+                let value = self.fetch_register_value()?;
+                let (negated, flags) = Flags::sub(0, value);
+                self.registers[ACC as usize] = negated;
+                self.flags = flags;
+            }
+            MinRegReg => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                self.registers[ACC as usize] = val_1.min(val_2);
+            }
+            MaxRegReg => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                self.registers[ACC as usize] = val_1.max(val_2);
+            }
+            LoadLocal => {
+                let offset = self.fetch16()? as i16;
+                let reg = self.fetch_register_id()?;
+                let addr = Ptr(self.registers[FP as usize].wrapping_add_signed(offset));
+                let value = self.read16(addr)?;
+                self.set_reg(reg, value)?;
+            }
+            StoreLocal => {
+                let value = self.fetch_register_value()?;
+                let offset = self.fetch16()? as i16;
+                let addr = Ptr(self.registers[FP as usize].wrapping_add_signed(offset));
+                self.write16(addr, value)?;
+            }
+            SubRegRegSigned => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                let (difference, flags) = Flags::sub(val_1, val_2);
+                self.registers[ACC as usize] = difference;
+                self.flags = flags;
+            }
+            CmpSignedRegReg => {
+                let val_1 = self.fetch_register_value()?;
+                let val_2 = self.fetch_register_value()?;
+                let (_, flags) = Flags::sub(val_1, val_2);
+                self.flags = flags;
+            }
+            JmpLtSigned => {
+                let addr = Ptr(self.fetch16()?);
+                if self.flags.signed_less() {
+                    self.registers[IP as usize] = addr.0;
+                }
+            }
+            JmpGeSigned => {
+                let addr = Ptr(self.fetch16()?);
+                if !self.flags.signed_less() {
+                    self.registers[IP as usize] = addr.0;
+                }
+            }
+            JmpAccZero => {
+                let addr = Ptr(self.fetch16()?);
+                if self.registers[ACC as usize] == 0 {
+                    self.registers[IP as usize] = addr.0;
+                }
+            }
+            JmpAccNonZero => {
+                let addr = Ptr(self.fetch16()?);
+                if self.registers[ACC as usize] != 0 {
+                    self.registers[IP as usize] = addr.0;
+                }
             }
             JmpNotEq => {
-                let value = self.fetch16();
-                let addr = Ptr(self.fetch16());
+                let value = self.fetch16()?;
+                let addr = Ptr(self.fetch16()?);
                 if value != self.registers[ACC as usize] {
                     self.registers[IP as usize] = addr.0;
                 }
             }
+            JmpRel => {
+                let offset = self.fetch16()? as i16;
+                self.jump_relative(offset);
+            }
+            JmpRelNotEq => {
+                let value = self.fetch16()?;
+                let offset = self.fetch16()? as i16;
+                if value != self.registers[ACC as usize] {
+                    self.jump_relative(offset);
+                }
+            }
+            Switch => {
+                let index = self.fetch_register_value()?;
+                let table = Ptr(self.fetch16()?);
+                let len = self.fetch16()?;
+                let default = self.fetch16()?;
+                self.registers[IP as usize] = if index < len {
+                    self.load16(Ptr(table.0.wrapping_add(index.wrapping_mul(2))))?
+                } else {
+                    default
+                };
+            }
             PushLit => {
-                let value = self.fetch16();
-                self.push(value);
+                let value = self.fetch16()?;
+                self.push(value)?;
             }
             PushReg => {
-                let reg = self.fetch_register_id()?;
-                let value = self.registers[reg as usize];
-                self.push(value);
+                let value = self.fetch_register_value()?;
+                self.push(value)?;
             }
             Pop => {
                 let reg = self.fetch_register_id()?;
-                self.registers[reg as usize] = self.pop();
+                let value = self.pop()?;
+                self.set_reg(reg, value)?;
+            }
+            PopAcc => {
+                self.registers[ACC as usize] = self.pop()?;
+            }
+            PushLit8 => {
+                let value = self.fetch()?;
+                self.push8(value)?;
+            }
+            PushReg8 => {
+                let value = self.fetch_register_value()?;
+                self.push8(value as u8)?;
+            }
+            Pop8 => {
+                let reg = self.fetch_register_id()?;
+                let value = self.pop8()?;
+                self.set_reg(reg, value as VMSize)?;
+            }
+            PushAll => {
+                self.push_registers()?;
+            }
+            PopAll => {
+                self.pop_registers()?;
             }
             CallLit => {
-                let subroutine_addr = self.fetch16();
-                self.push_state();
-                self.registers[IP as usize] = subroutine_addr;
+                let subroutine_addr = self.fetch16()?;
+                self.call(subroutine_addr, 0)?;
+            }
+            CallLitArgs => {
+                let subroutine_addr = self.fetch16()?;
+                let n_args = self.fetch()?;
+                self.call(subroutine_addr, n_args as u16)?;
             }
             CallReg => {
-                let reg = self.fetch_register_id()?;
-                let subroutine_addr = self.registers[reg as usize];
-                self.push_state();
+                let subroutine_addr = self.fetch_register_value()?;
+                self.call(subroutine_addr, 0)?;
+            }
+            TailCall => {
+                let subroutine_addr = self.fetch16()?;
+                // Drop anything pushed in the current frame, the saved state above the
+                // FP still returns to the caller's caller
+                self.registers[SP as usize] = self.registers[FP as usize];
+                self.stack_frame_size = FrameSize::default();
                 self.registers[IP as usize] = subroutine_addr;
             }
+            MemCopy => {
+                let src = Ptr(self.fetch16()?);
+                let dest = Ptr(self.fetch16()?);
+                let words = self.fetch16()?;
+                self.copy_words(src, dest, words)?;
+            }
+            MemSet => {
+                let dest = Ptr(self.fetch16()?);
+                let value = self.fetch16()?;
+                let words = self.fetch16()?;
+                self.fill_words(dest, value, words)?;
+            }
             Ret => {
-                self.pop_state();
+                self.pop_state()?;
+            }
+            Int => {
+                let int_no = self.fetch()?;
+                self.interrupt(int_no)?;
+            }
+            Iret => {
+                self.pop_state()?;
+                self.registers[ACC as usize] = self.pop()?;
+                self.flags = Flags::from_bits(self.pop()?);
+            }
+            Syscall => {
+                let number = self.fetch()?;
+                let handler = self
+                    .syscalls
+                    .get(number as usize)
+                    .copied()
+                    .flatten()
+                    .ok_or(MachineError::InvalidSyscall(number))?;
+                handler(self)?;
+            }
+            Hlt => {
+                self.halted = true;
+                self.exit_code = Some(self.registers[ACC as usize] as u8);
             }
-            _ => todo!(),
+            unimplemented => return Err(MachineError::Unimplemented(unimplemented.into())),
         }
         Ok(())
     }
 
+    /// Runs the next instruction, or services the next pending interrupt. A step which
+    /// faults is handed to the fault handler, if one is set, to decide what happens next
     pub fn step(&mut self) -> Result<(), MachineError> {
-        let instruction = self.fetch().try_into()?;
-        self.execute(instruction)
+        let mut retries = 0;
+        loop {
+            if self.halted {
+                return Ok(());
+            }
+            let at = self.registers[IP as usize];
+            // Pending interrupts are serviced ahead of the instruction at the IP
+            let servicing_interrupt = !self.interrupts.is_empty();
+            let fault = match self.step_journaled() {
+                Ok(()) => return Ok(()),
+                Err(fault) => fault,
+            };
+            let Some(handler) = self.fault_handler else {
+                return Err(fault);
+            };
+            // A partly fetched instruction leaves the IP mid way through it, so the
+            // handler sees it back at the start and a retry reruns the whole step
+            self.registers[IP as usize] = at;
+            match handler(self, &fault) {
+                FaultAction::Retry if retries < FAULT_RETRY_LIMIT => retries += 1,
+                FaultAction::Skip if !servicing_interrupt => {
+                    let len = self.instruction_len_at(Ptr(at)).unwrap_or(1);
+                    self.registers[IP as usize] = at.wrapping_add(len as u16);
+                    return Ok(());
+                }
+                // Propagating, retrying too often or skipping an instruction which never ran
+                _ => return Err(fault),
+            }
+        }
+    }
+
+    /// Sets the host function which decides what happens when a step faults, or clears
+    /// it so faults are returned from `step` straight away
+    pub fn set_fault_handler(&mut self, handler: Option<FaultHandler<MEMORY, EXTRA_GPR>>) {
+        self.fault_handler = handler;
+    }
+
+    fn step_journaled(&mut self) -> Result<(), MachineError> {
+        if self.journal.is_none() {
+            return self.step_unjournaled();
+        }
+        let registers = self.registers;
+        self.pending = Some(UndoRecord {
+            registers: Vec::new(),
+            extra_registers: self.extra_registers,
+            memory: Vec::new(),
+            flags: self.flags,
+            halted: self.halted,
+            exit_code: self.exit_code,
+            stack_frame_size: self.stack_frame_size,
+            interrupt: self.interrupts.front().copied(),
+            opcode: None,
+            overflowed: false,
+        });
+        // A step which fails part way is still journaled, so it can be backed out of
+        let result = self.step_unjournaled();
+        if let Some(record) = self.pending.take() {
+            self.journal_step(registers, record);
+        }
+        result
+    }
+
+    fn step_unjournaled(&mut self) -> Result<(), MachineError> {
+        match self.interrupts.pop_front() {
+            Some(int_no) => self.interrupt(int_no)?,
+            None => {
+                self.step_returning()?;
+            }
+        }
+        for mapped in self.devices.iter_mut() {
+            mapped.device.tick();
+        }
+        Ok(())
+    }
+
+    /// Executes one instruction given as raw bytes as though it sat at the IP, without
+    /// writing it into memory. The IP is put back afterwards unless the instruction moved
+    /// it somewhere other than just past its own bytes
+    pub fn execute_bytes(&mut self, bytes: &[u8]) -> Result<(), MachineError> {
+        let ip = self.registers[IP as usize];
+        let result = self.execute_staged(bytes);
+        let end = self.registers[IP as usize];
+        if end > ip && end as usize <= ip as usize + bytes.len() {
+            self.registers[IP as usize] = ip;
+        }
+        result
+    }
+
+    /// Fetches and decodes the instruction at the IP, moving the IP past it, without
+    /// executing it. Hand the result to `execute_decoded` to run it
+    pub fn decode(&mut self) -> Result<DecodedInstruction, MachineError> {
+        let addr = Ptr(self.registers[IP as usize]);
+        let (instruction, operands) = self.decode_at(addr)?;
+        self.registers[IP as usize] += instruction.encoded_len() as VMSize;
+        Ok(DecodedInstruction {
+            addr,
+            instruction,
+            operands,
+        })
+    }
+
+    /// Executes an instruction from `decode` with the operands it holds, as though it
+    /// still sat at the address it was decoded from, leaving the IP wherever it moves it
+    pub fn execute_decoded(&mut self, decoded: DecodedInstruction) -> Result<(), MachineError> {
+        let bytes = encode(decoded.instruction, &decoded.operands).map_err(|_| {
+            MachineError::InvalidOperands {
+                opcode: decoded.instruction.into(),
+                at: decoded.addr,
+            }
+        })?;
+        self.registers[IP as usize] = decoded.addr.0;
+        self.execute_staged(&bytes)
+    }
+
+    /// Stages `bytes` to be fetched ahead of memory and steps the instruction they hold at
+    /// the IP. Bytes past the end of the instruction are dropped rather than left staged
+    fn execute_staged(&mut self, bytes: &[u8]) -> Result<(), MachineError> {
+        self.check_bounds(Ptr(self.registers[IP as usize]), bytes.len())?;
+        if bytes.len() > MAX_INSTRUCTION_LENGTH {
+            return Err(MachineError::ProgramTooLarge);
+        }
+        let len = match bytes.first().map(|&opcode| Instructions::try_from(opcode)) {
+            Some(Ok(instruction)) => instruction.encoded_len().min(bytes.len()),
+            _ => bytes.len().min(1),
+        };
+        self.staged.clear();
+        for &byte in &bytes[..len] {
+            // The length was checked against the capacity above
+            let _ = self.staged.push_back(byte);
+        }
+        let result = self.step_returning();
+        self.staged.clear();
+        result.map(|_| ())
+    }
+
+    /// Fetches and executes the instruction at the IP, returning the instruction which ran.
+    /// Unlike `step` this always executes an instruction, so pending interrupts and the
+    /// halted flag are left for the caller
+    pub fn step_returning(&mut self) -> Result<Instructions, MachineError> {
+        let registers = self.registers;
+        let at = Ptr(registers[IP as usize]);
+        let opcode = self.fetch()?;
+        let instruction = Instructions::try_from(opcode)
+            .map_err(|_| MachineError::InvalidInstruction { opcode, at })?;
+        self.histogram[opcode as usize] += 1;
+        if let Some(coverage) = &mut self.coverage {
+            coverage[at.0 as usize / 8] |= 1 << (at.0 % 8);
+        }
+        if let Some(record) = &mut self.pending {
+            record.opcode = Some(opcode);
+        }
+        if self.events.is_none() {
+            self.execute(instruction)?;
+            return Ok(instruction);
+        }
+        self.emit(MachineEvent::InstructionExecuted { at, instruction });
+        let result = self.execute(instruction);
+        let changed = self.registers;
+        for (id, (&old, &new)) in registers.iter().zip(&changed).enumerate() {
+            if old != new {
+                let register = Registers::try_from(id as u8)
+                    .expect("index should not be able to exceed register count");
+                self.emit(MachineEvent::RegisterChanged(RegisterChange {
+                    register,
+                    old,
+                    new,
+                }));
+            }
+        }
+        result?;
+        Ok(instruction)
+    }
+
+    /// Starts or stops recording each step so it can be undone with `step_back`.
+    /// Stopping discards everything recorded so far
+    pub fn set_journaling(&mut self, enabled: bool) {
+        if !enabled {
+            self.journal = None;
+        } else if self.journal.is_none() {
+            self.journal = Some(Deque::new());
+        }
+    }
+
+    fn journal_step(
+        &mut self,
+        registers: [VMSize; REGISTER_COUNT as usize],
+        mut record: UndoRecord<EXTRA_GPR>,
+    ) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        if record.overflowed {
+            // This step can't be undone, so neither can anything before it
+            journal.clear();
+            return;
+        }
+        for (id, (&old, &new)) in registers.iter().zip(&self.registers).enumerate() {
+            if old != new {
+                let register = Registers::try_from(id as u8)
+                    .expect("index should not be able to exceed register count");
+                // Capacity matches the register count so this can't fail
+                let _ = record.registers.push(RegisterChange { register, old, new });
+            }
+        }
+        if journal.is_full() {
+            journal.pop_front();
+        }
+        let _ = journal.push_back(record);
+    }
+
+    /// Undoes the most recent journaled step, returning false when there's nothing left to
+    /// undo. Devices aren't journaled, so they keep any reads, writes and ticks of the step
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.journal.as_mut().and_then(|journal| journal.pop_back()) else {
+            return false;
+        };
+        // Writes are undone newest first so repeated writes to a byte end on its oldest value
+        for change in record.memory.iter().rev() {
+            self.memory[change.addr.0 as usize] = change.old;
+        }
+        for change in &record.registers {
+            self.registers[change.register as usize] = change.old;
+        }
+        self.extra_registers = record.extra_registers;
+        self.flags = record.flags;
+        self.halted = record.halted;
+        self.exit_code = record.exit_code;
+        self.stack_frame_size = record.stack_frame_size;
+        if let Some(int_no) = record.interrupt {
+            // The step took this from the front of the queue, so there's room to put it back
+            let _ = self.interrupts.push_front(int_no);
+        }
+        if let Some(opcode) = record.opcode {
+            self.histogram[opcode as usize] -= 1;
+        }
+        true
+    }
+
+    /// How many times each opcode has been stepped, indexed by opcode
+    pub fn instruction_histogram(&self) -> &[u64; 256] {
+        &self.histogram
+    }
+
+    /// Steps the machine up to `n` times, stopping early once it halts,
+    /// and returns the number of steps which actually ran
+    pub fn step_n(&mut self, n: usize) -> Result<usize, MachineError> {
+        let mut steps = 0;
+        while steps < n && !self.halted {
+            self.step()?;
+            steps += 1;
+        }
+        Ok(steps)
+    }
+
+    /// Steps the machine until `condition` holds, it halts or `max_steps` steps have run.
+    /// The condition is checked before every step, so one which already holds runs nothing
+    pub fn run_until<F: Fn(&Self) -> bool>(
+        &mut self,
+        condition: F,
+        max_steps: usize,
+    ) -> Result<RunOutcome, MachineError> {
+        for steps in 0..max_steps {
+            if condition(self) {
+                return Ok(RunOutcome::Reached(steps));
+            }
+            if self.halted {
+                return Ok(RunOutcome::Halted(steps));
+            }
+            self.step()?;
+        }
+        if condition(self) {
+            return Ok(RunOutcome::Reached(max_steps));
+        }
+        if self.halted {
+            return Ok(RunOutcome::Halted(max_steps));
+        }
+        Ok(RunOutcome::StepLimit)
+    }
+
+    /// Steps the machine until it halts or `limit` steps have run. With `detect_loops` set,
+    /// the register file after each of the last `LOOP_HISTORY_LENGTH` steps is remembered and
+    /// the run stops early if it repeats, which catches tight loops that change nothing
+    pub fn run_with_limit(
+        &mut self,
+        limit: usize,
+        detect_loops: bool,
+    ) -> Result<RunOutcome, MachineError> {
+        let mut history: Deque<[VMSize; REGISTER_COUNT as usize], LOOP_HISTORY_LENGTH> =
+            Deque::new();
+        for steps in 0..limit {
+            if self.halted {
+                return Ok(RunOutcome::Halted(steps));
+            }
+            self.step()?;
+            if detect_loops && !self.halted {
+                if history.iter().any(|seen| *seen == self.registers) {
+                    return Ok(RunOutcome::SuspectedLoop {
+                        at: Ptr(self.registers[IP as usize]),
+                    });
+                }
+                if history.is_full() {
+                    history.pop_front();
+                }
+                let _ = history.push_back(self.registers);
+            }
+        }
+        if self.halted {
+            return Ok(RunOutcome::Halted(limit));
+        }
+        Ok(RunOutcome::StepLimit)
     }
 }
 
-impl<const MEMORY: usize> fmt::Debug for Machine<MEMORY>
-where
-    [(); MEMORY * mem::size_of::<u8>()]:,
-{
+impl<const MEMORY: usize, const EXTRA_GPR: usize> fmt::Debug for Machine<MEMORY, EXTRA_GPR> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut result = f.debug_struct("Machine");
         for i in 0..REGISTER_COUNT {
             let register =
                 Registers::try_from(i).expect("index should not be able to exceed register count");
-            let mut register_name: String<3> = String::new();
-            write!(register_name, "{register:?}")?;
-            let mut register_value: String<6> = String::new();
+            let mut register_value: String<REGISTER_VALUE_WIDTH> = String::new();
             write!(
                 register_value,
                 "{:#06X?}",
                 self.registers[register as usize]
             )?;
-            result.field(&register_name, &register_value);
+            result.field(register.name(), &register_value);
         }
-        result.field("memory(bytes)", &self.memory.len()).finish()
+        // Instructions can't name the extra registers, so they're listed by index
+        // rather than given register names
+        let mut extra_registers: Vec<String<REGISTER_VALUE_WIDTH>, EXTRA_GPR> = Vec::new();
+        for value in self.extra_registers.iter() {
+            let mut register_value: String<REGISTER_VALUE_WIDTH> = String::new();
+            write!(register_value, "{value:#06X?}")?;
+            // Capacity matches the extra register count so this can't fail
+            let _ = extra_registers.push(register_value);
+        }
+        if EXTRA_GPR > 0 {
+            result.field("extra_registers", &extra_registers);
+        }
+        result
+            .field("halted", &self.halted)
+            .field("exit_code", &self.exit_code)
+            .field("flags", &self.flags)
+            .field("memory(bytes)", &self.memory.len())
+            .finish()
+    }
+}
+
+/// Loads a program at address zero of a fresh `N` byte machine and runs it until it
+/// halts, returning the machine for inspection. A program which runs off the end of the
+/// image fails with `OutOfBounds` rather than panicking
+pub fn run_program<const N: usize>(bytes: &[u8]) -> Result<Machine<N>, MachineError> {
+    let mut machine = Machine::new();
+    machine.load_program(Ptr(0), bytes)?;
+    match machine.run_with_limit(RUN_PROGRAM_STEP_LIMIT, false)? {
+        RunOutcome::Halted(_) => Ok(machine),
+        _ => Err(MachineError::StepLimitReached),
     }
 }
 
@@ -2,14 +2,28 @@ use crate::Ptr;
 
 use core::fmt;
 
+/// The number of bytes formatted on each row unless overridden with `with_width`
+pub const DEFAULT_WINDOW_WIDTH: usize = 8;
+
 pub struct MemoryWindow<'a> {
     pub(crate) addr: Ptr,
     pub(crate) data: &'a [u8],
+    pub(crate) cols: usize,
 }
 
 impl<'a> MemoryWindow<'a> {
     pub fn new(addr: Ptr, data: &'a [u8]) -> Self {
-        MemoryWindow { addr, data }
+        MemoryWindow {
+            addr,
+            data,
+            cols: DEFAULT_WINDOW_WIDTH,
+        }
+    }
+
+    /// Sets how many bytes are formatted on each row
+    pub fn with_width(mut self, cols: usize) -> Self {
+        self.cols = cols.max(1);
+        self
     }
 
     pub fn ptr(&self) -> Ptr {
@@ -19,15 +33,20 @@ impl<'a> MemoryWindow<'a> {
     pub fn data(&self) -> &'a [u8] {
         self.data
     }
-}
 
-impl<'a> fmt::Debug for MemoryWindow<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// Formats the window a byte at a time straight into `f`, letting `annotate` emit
+    /// a marker ahead of each byte
+    pub(crate) fn fmt_annotated<W: fmt::Write + ?Sized>(
+        &self,
+        f: &mut W,
+        annotate: impl Fn(&mut W, Ptr) -> fmt::Result,
+    ) -> fmt::Result {
         // Emit the initial address of the region
         write!(f, "[{:?}] ", self.addr)?;
         let len = self.data.len();
         let mut i = 0;
         while i < len {
+            annotate(f, self.addr + i)?;
             if self.data[i] == 0 {
                 // Emit dashes
                 write!(f, "------")?;
@@ -41,13 +60,13 @@ impl<'a> fmt::Debug for MemoryWindow<'a> {
                 break;
             }
             // Emit next block address or spacer for values
-            if i % 8 == 0 {
+            if i % self.cols == 0 {
                 write!(f, "\n[{:?}] ", self.addr + i)?;
             } else {
                 write!(f, " ")?;
             }
         }
-        while i % 8 != 0 {
+        while i % self.cols != 0 {
             // Finish the last line out with placeholders
             write!(f, " _x__")?;
             i += 1;
@@ -55,3 +74,40 @@ impl<'a> fmt::Debug for MemoryWindow<'a> {
         Ok(())
     }
 }
+
+impl<'a> fmt::Debug for MemoryWindow<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_annotated(f, |_, _| Ok(()))
+    }
+}
+
+/// A memory window which marks the bytes the IP, SP and FP point at when formatted
+pub struct AnnotatedWindow<'a> {
+    pub(crate) window: MemoryWindow<'a>,
+    pub(crate) ip: Ptr,
+    pub(crate) sp: Ptr,
+    pub(crate) fp: Ptr,
+}
+
+impl<'a> AnnotatedWindow<'a> {
+    pub fn new(window: MemoryWindow<'a>, ip: Ptr, sp: Ptr, fp: Ptr) -> Self {
+        AnnotatedWindow { window, ip, sp, fp }
+    }
+
+    pub fn window(&self) -> &MemoryWindow<'a> {
+        &self.window
+    }
+}
+
+impl<'a> fmt::Debug for AnnotatedWindow<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.window.fmt_annotated(f, |f, addr| {
+            for (name, ptr) in [("IP", self.ip), ("SP", self.sp), ("FP", self.fp)] {
+                if addr == ptr {
+                    write!(f, "{name}>")?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,162 @@
+use heapless::Vec;
+
+use crate::{Instructions, Instructions::*, MachineError, Ptr, Registers};
+
+use OperandKind::*;
+
+/// The most operands any instruction reads, those of `Switch` and `MoveMemToRegIndexed`
+pub const MAX_OPERANDS: usize = 4;
+
+/// The kinds of operand an instruction can read from the bytes following its opcode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OperandKind {
+    /// A single byte register identity
+    Reg,
+    /// A single byte literal value
+    Lit8,
+    /// A two byte literal value
+    Lit16,
+    /// A two byte memory address
+    Addr,
+}
+
+impl OperandKind {
+    /// The number of instruction bytes the operand occupies
+    pub const fn size(self) -> usize {
+        match self {
+            Reg | Lit8 => 1,
+            Lit16 | Addr => 2,
+        }
+    }
+}
+
+/// An operand value, tagged with the kind of operand it encodes as
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operand {
+    Reg(Registers),
+    Lit8(u8),
+    Lit16(u16),
+    Addr(Ptr),
+}
+
+impl Operand {
+    pub fn kind(&self) -> OperandKind {
+        match self {
+            Operand::Reg(_) => Reg,
+            Operand::Lit8(_) => Lit8,
+            Operand::Lit16(_) => Lit16,
+            Operand::Addr(_) => Addr,
+        }
+    }
+}
+
+/// An instruction fetched with all of its operands but not yet executed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DecodedInstruction {
+    /// Where the instruction's opcode was fetched from
+    pub addr: Ptr,
+    pub instruction: Instructions,
+    pub operands: Vec<Operand, MAX_OPERANDS>,
+}
+
+impl Instructions {
+    /// The operands read by the instruction, in the order they follow the opcode
+    pub const fn operand_layout(&self) -> &'static [OperandKind] {
+        match self {
+            Nop => &[],
+            MoveLitToReg => &[Lit16, Reg],
+            MoveRegToReg => &[Reg, Reg],
+            MoveRegToMem => &[Reg, Addr],
+            MoveMemToReg => &[Addr, Reg],
+            AddRegReg => &[Reg, Reg],
+            JmpNotEq => &[Lit16, Addr],
+            PushLit => &[Lit16],
+            PushReg => &[Reg],
+            Pop => &[Reg],
+            PushAll => &[],
+            PopAll => &[],
+            JmpRel => &[Lit16],
+            JmpRelNotEq => &[Lit16, Lit16],
+            MemCopy => &[Addr, Addr, Lit16],
+            MemSet => &[Addr, Lit16, Lit16],
+            MoveLitToRegByte => &[Lit8, Reg],
+            MoveMemToRegByte => &[Addr, Reg],
+            MoveRegToMemByte => &[Reg, Addr],
+            Switch => &[Reg, Addr, Lit16, Addr],
+            MulRegReg => &[Reg, Reg],
+            AddRegRegDest => &[Reg, Reg, Reg],
+            MinRegReg => &[Reg, Reg],
+            MaxRegReg => &[Reg, Reg],
+            LoadLocal => &[Lit16, Reg],
+            StoreLocal => &[Reg, Lit16],
+            SubRegRegSigned => &[Reg, Reg],
+            CmpSignedRegReg => &[Reg, Reg],
+            JmpLtSigned => &[Addr],
+            JmpGeSigned => &[Addr],
+            Adc => &[Reg, Reg],
+            JmpAccZero => &[Addr],
+            JmpAccNonZero => &[Addr],
+            DivRegReg => &[Reg, Reg],
+            PushLit8 => &[Lit8],
+            PushReg8 => &[Reg],
+            Pop8 => &[Reg],
+            MoveAccToReg => &[Reg],
+            PopAcc => &[],
+            MoveMemToRegIndexed => &[Reg, Reg, Lit16, Reg],
+            MoveMemToRegByteSigned => &[Addr, Reg],
+            Lea => &[Addr, Reg],
+            SwapReg => &[Reg, Reg],
+            NegReg => &[Reg],
+            CallLit => &[Addr],
+            CallReg => &[Reg],
+            Ret => &[],
+            Int => &[Lit8],
+            Iret => &[],
+            Syscall => &[Lit8],
+            TailCall => &[Addr],
+            CallLitArgs => &[Addr, Lit8],
+            Reserved => &[],
+            Hlt => &[],
+        }
+    }
+
+    /// Decodes the operands from `bytes`, which start just after the opcode, following the
+    /// instruction's `operand_layout`. Fails if `bytes` ends before the last operand or a
+    /// register operand names no register
+    pub fn decode_operands(
+        &self,
+        bytes: &[u8],
+    ) -> Result<Vec<Operand, MAX_OPERANDS>, MachineError> {
+        let layout = self.operand_layout();
+        if bytes.len() < self.encoded_len() - 1 {
+            return Err(MachineError::BufferTooSmall);
+        }
+        let mut operands = Vec::new();
+        let mut pos = 0;
+        for &kind in layout {
+            let word = || u16::from_be_bytes([bytes[pos], bytes[pos + 1]]);
+            let operand = match kind {
+                Reg => Operand::Reg(bytes[pos].try_into()?),
+                Lit8 => Operand::Lit8(bytes[pos]),
+                Lit16 => Operand::Lit16(word()),
+                Addr => Operand::Addr(Ptr(word())),
+            };
+            // No layout is longer than MAX_OPERANDS so the push can't fail
+            let _ = operands.push(operand);
+            pos += kind.size();
+        }
+        Ok(operands)
+    }
+
+    /// The number of bytes the instruction occupies, including its opcode
+    pub const fn encoded_len(&self) -> usize {
+        let layout = self.operand_layout();
+        let mut len = 1;
+        let mut i = 0;
+        while i < layout.len() {
+            len += layout[i].size();
+            i += 1;
+        }
+        len
+    }
+}
@@ -0,0 +1,277 @@
+use heapless::Vec;
+
+use crate::{
+    encode, Instructions, Instructions::*, MachineError, Operand, Operand::*, Ptr, Registers,
+    VMSize,
+};
+
+/// Fluent alternative to hand encoding programs byte by byte with `set8`
+#[derive(Default)]
+pub struct ProgramBuilder<const N: usize> {
+    bytes: Vec<u8, N>,
+    overflowed: bool,
+    /// The first instruction which failed to encode, reported by `build`
+    error: Option<MachineError>,
+}
+
+impl<const N: usize> ProgramBuilder<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodes any instruction from its operands with `encode`, for instructions the
+    /// builder has no method of their own for. Operands which don't match the
+    /// instruction's `operand_layout` fail the `build` with `InvalidOperands`, and
+    /// reserved opcodes, which the machine won't run, with `Unimplemented`
+    pub fn emit(mut self, instruction: Instructions, operands: &[Operand]) -> Self {
+        let encoded = match instruction {
+            Reserved => Err(MachineError::Unimplemented(instruction.into())),
+            _ => encode(instruction, operands).map_err(|_| MachineError::InvalidOperands {
+                opcode: instruction.into(),
+                at: Ptr(self.bytes.len() as VMSize),
+            }),
+        };
+        match encoded {
+            Ok(bytes) => self.overflowed |= self.bytes.extend_from_slice(&bytes).is_err(),
+            Err(error) if self.error.is_none() => self.error = Some(error),
+            Err(_) => {}
+        }
+        self
+    }
+
+    pub fn nop(self) -> Self {
+        self.emit(Nop, &[])
+    }
+
+    pub fn mov_lit_reg(self, value: u16, reg: Registers) -> Self {
+        self.emit(MoveLitToReg, &[Lit16(value), Reg(reg)])
+    }
+
+    pub fn mov_reg_reg(self, src: Registers, dest: Registers) -> Self {
+        self.emit(MoveRegToReg, &[Reg(src), Reg(dest)])
+    }
+
+    pub fn mov_reg_mem(self, src: Registers, addr: Ptr) -> Self {
+        self.emit(MoveRegToMem, &[Reg(src), Addr(addr)])
+    }
+
+    pub fn mov_acc_reg(self, dest: Registers) -> Self {
+        self.emit(MoveAccToReg, &[Reg(dest)])
+    }
+
+    pub fn mov_mem_reg(self, addr: Ptr, dest: Registers) -> Self {
+        self.emit(MoveMemToReg, &[Addr(addr), Reg(dest)])
+    }
+
+    pub fn mov_mem_reg_indexed(
+        self,
+        base: Registers,
+        index: Registers,
+        displacement: i16,
+        dest: Registers,
+    ) -> Self {
+        self.emit(
+            MoveMemToRegIndexed,
+            &[Reg(base), Reg(index), Lit16(displacement as u16), Reg(dest)],
+        )
+    }
+
+    pub fn mov_lit_reg_byte(self, value: u8, reg: Registers) -> Self {
+        self.emit(MoveLitToRegByte, &[Lit8(value), Reg(reg)])
+    }
+
+    pub fn mov_mem_reg_byte(self, addr: Ptr, dest: Registers) -> Self {
+        self.emit(MoveMemToRegByte, &[Addr(addr), Reg(dest)])
+    }
+
+    pub fn mov_mem_reg_byte_signed(self, addr: Ptr, dest: Registers) -> Self {
+        self.emit(MoveMemToRegByteSigned, &[Addr(addr), Reg(dest)])
+    }
+
+    pub fn swap_reg(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(SwapReg, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn neg_reg(self, reg: Registers) -> Self {
+        self.emit(NegReg, &[Reg(reg)])
+    }
+
+    pub fn lea(self, addr: Ptr, dest: Registers) -> Self {
+        self.emit(Lea, &[Addr(addr), Reg(dest)])
+    }
+
+    pub fn mov_reg_mem_byte(self, src: Registers, addr: Ptr) -> Self {
+        self.emit(MoveRegToMemByte, &[Reg(src), Addr(addr)])
+    }
+
+    pub fn add(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(AddRegReg, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn adc(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(Adc, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn add_dest(self, reg_1: Registers, reg_2: Registers, dest: Registers) -> Self {
+        self.emit(AddRegRegDest, &[Reg(reg_1), Reg(reg_2), Reg(dest)])
+    }
+
+    pub fn mul(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(MulRegReg, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn div(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(DivRegReg, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn min(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(MinRegReg, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn max(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(MaxRegReg, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn load_local(self, offset: i16, dest: Registers) -> Self {
+        self.emit(LoadLocal, &[Lit16(offset as u16), Reg(dest)])
+    }
+
+    pub fn store_local(self, src: Registers, offset: i16) -> Self {
+        self.emit(StoreLocal, &[Reg(src), Lit16(offset as u16)])
+    }
+
+    pub fn sub_signed(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(SubRegRegSigned, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn cmp_signed(self, reg_1: Registers, reg_2: Registers) -> Self {
+        self.emit(CmpSignedRegReg, &[Reg(reg_1), Reg(reg_2)])
+    }
+
+    pub fn jlt_signed(self, addr: Ptr) -> Self {
+        self.emit(JmpLtSigned, &[Addr(addr)])
+    }
+
+    pub fn jge_signed(self, addr: Ptr) -> Self {
+        self.emit(JmpGeSigned, &[Addr(addr)])
+    }
+
+    pub fn jz(self, addr: Ptr) -> Self {
+        self.emit(JmpAccZero, &[Addr(addr)])
+    }
+
+    pub fn jnz(self, addr: Ptr) -> Self {
+        self.emit(JmpAccNonZero, &[Addr(addr)])
+    }
+
+    pub fn jne(self, value: u16, addr: Ptr) -> Self {
+        self.emit(JmpNotEq, &[Lit16(value), Addr(addr)])
+    }
+
+    pub fn switch(self, index: Registers, table: Ptr, len: u16, default: Ptr) -> Self {
+        self.emit(
+            Switch,
+            &[Reg(index), Addr(table), Lit16(len), Addr(default)],
+        )
+    }
+
+    pub fn push_lit(self, value: u16) -> Self {
+        self.emit(PushLit, &[Lit16(value)])
+    }
+
+    pub fn push_reg(self, reg: Registers) -> Self {
+        self.emit(PushReg, &[Reg(reg)])
+    }
+
+    pub fn pop(self, reg: Registers) -> Self {
+        self.emit(Pop, &[Reg(reg)])
+    }
+
+    pub fn pop_acc(self) -> Self {
+        self.emit(PopAcc, &[])
+    }
+
+    pub fn push_lit8(self, value: u8) -> Self {
+        self.emit(PushLit8, &[Lit8(value)])
+    }
+
+    pub fn push_reg8(self, reg: Registers) -> Self {
+        self.emit(PushReg8, &[Reg(reg)])
+    }
+
+    pub fn pop8(self, reg: Registers) -> Self {
+        self.emit(Pop8, &[Reg(reg)])
+    }
+
+    pub fn push_all(self) -> Self {
+        self.emit(PushAll, &[])
+    }
+
+    pub fn pop_all(self) -> Self {
+        self.emit(PopAll, &[])
+    }
+
+    pub fn jmp_rel(self, offset: i16) -> Self {
+        self.emit(JmpRel, &[Lit16(offset as u16)])
+    }
+
+    pub fn jne_rel(self, value: u16, offset: i16) -> Self {
+        self.emit(JmpRelNotEq, &[Lit16(value), Lit16(offset as u16)])
+    }
+
+    pub fn mem_copy(self, src: Ptr, dest: Ptr, words: u16) -> Self {
+        self.emit(MemCopy, &[Addr(src), Addr(dest), Lit16(words)])
+    }
+
+    pub fn mem_set(self, dest: Ptr, value: u16, words: u16) -> Self {
+        self.emit(MemSet, &[Addr(dest), Lit16(value), Lit16(words)])
+    }
+
+    pub fn call_lit(self, addr: Ptr) -> Self {
+        self.emit(CallLit, &[Addr(addr)])
+    }
+
+    pub fn call_lit_args(self, addr: Ptr, n_args: u8) -> Self {
+        self.emit(CallLitArgs, &[Addr(addr), Lit8(n_args)])
+    }
+
+    pub fn call_reg(self, reg: Registers) -> Self {
+        self.emit(CallReg, &[Reg(reg)])
+    }
+
+    pub fn tail_call(self, addr: Ptr) -> Self {
+        self.emit(TailCall, &[Addr(addr)])
+    }
+
+    pub fn ret(self) -> Self {
+        self.emit(Ret, &[])
+    }
+
+    pub fn int(self, int_no: u8) -> Self {
+        self.emit(Int, &[Lit8(int_no)])
+    }
+
+    pub fn iret(self) -> Self {
+        self.emit(Iret, &[])
+    }
+
+    pub fn syscall(self, number: u8) -> Self {
+        self.emit(Syscall, &[Lit8(number)])
+    }
+
+    pub fn hlt(self) -> Self {
+        self.emit(Hlt, &[])
+    }
+
+    /// Emits the encoded program, failing if an instruction's operands didn't encode or
+    /// the program didn't fit in `N` bytes
+    pub fn build(self) -> Result<Vec<u8, N>, MachineError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if self.overflowed {
+            return Err(MachineError::ProgramTooLarge);
+        }
+        Ok(self.bytes)
+    }
+}
@@ -8,6 +8,8 @@ use derive_more::*;
     Copy,
     Eq,
     PartialEq,
+    Ord,
+    PartialOrd,
     Sub,
     BitAnd,
     BitOr,
@@ -49,13 +51,23 @@ impl Ptr {
         (*self) = (*self) + v;
         Ptr(curr) // Return the previous position
     }
+
+    /// Adds to the pointer, clamping at the top of the address space
+    pub fn saturating_add(self, n: u16) -> Ptr {
+        Ptr(self.0.saturating_add(n))
+    }
+
+    /// Subtracts from the pointer, clamping at the bottom of the address space
+    pub fn saturating_sub(self, n: u16) -> Ptr {
+        Ptr(self.0.saturating_sub(n))
+    }
 }
 
 macro_rules! add_to_ptr {
     ($($type:ty),*) => {$(
         impl Add<$type> for Ptr {
             type Output = Ptr;
-        
+
             fn add(self, other: $type) -> Ptr {
                 Ptr(self.0 + other as u16)
             }
@@ -0,0 +1,45 @@
+use crate::{Instructions, OperandKind, Ptr, Registers};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum VerifyError {
+    /// An opcode which doesn't decode to any instruction
+    InvalidInstruction { opcode: u8, at: Ptr },
+    /// A register operand which doesn't identify any register
+    InvalidRegister { id: u8, at: Ptr },
+    /// An instruction whose operands run past the end of the verified region
+    Truncated { at: Ptr },
+    /// A region which runs past the end of the memory provided
+    OutOfBounds(Ptr),
+}
+
+/// Walks the instruction stream in `mem[start..start + len]` without executing it,
+/// checking every opcode and register operand is valid and that no instruction
+/// runs off the end of the region
+pub fn verify(mem: &[u8], start: Ptr, len: usize) -> Result<(), VerifyError> {
+    let mut pos = start.0 as usize;
+    let end = pos + len;
+    if end > mem.len() {
+        return Err(VerifyError::OutOfBounds(start));
+    }
+    while pos < end {
+        let at = Ptr(pos as u16);
+        let opcode = mem[pos];
+        let instruction = Instructions::try_from(opcode)
+            .map_err(|_| VerifyError::InvalidInstruction { opcode, at })?;
+        if pos + instruction.encoded_len() > end {
+            return Err(VerifyError::Truncated { at });
+        }
+        pos += 1;
+        for kind in instruction.operand_layout() {
+            if *kind == OperandKind::Reg {
+                let id = mem[pos];
+                Registers::try_from(id).map_err(|_| VerifyError::InvalidRegister {
+                    id,
+                    at: Ptr(pos as u16),
+                })?;
+            }
+            pos += kind.size();
+        }
+    }
+    Ok(())
+}